@@ -0,0 +1,42 @@
+// Runs a small program without any frontend and prints the CPU registers
+// before every instruction.
+//
+// Run with: cargo run --example headless_trace
+
+use nes_emulator::prelude::*;
+
+pub fn main() {
+    let bus = Bus::new(ROM::empty());
+    let mut cpu = CPU::new(bus);
+
+    // Sum 1..=5 into the accumulator
+    let program = vec![
+        0xA9, 0x00, // LDA #$00
+        0xA2, 0x05, // LDX #$05
+        0x86, 0x10, // STX $10
+        0x18,       // CLC
+        0x65, 0x10, // ADC $10
+        0xCA,       // DEX
+        0xD0, 0xF8, // BNE -8
+        0x00,       // BRK
+    ];
+
+    cpu.load_program(program);
+    cpu.reset();
+    let result = cpu.execute_with_callback(|cpu| {
+        println!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            cpu.program_counter,
+            cpu.register_accumulator,
+            cpu.index_register_x,
+            cpu.index_register_y,
+            cpu.status.status,
+            cpu.stack_pointer,
+        );
+    });
+
+    match result {
+        Ok(_) => println!("Result: {}", cpu.register_accumulator),
+        Err(e) => println!("Stopped: {}", e),
+    }
+}
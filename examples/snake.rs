@@ -0,0 +1,178 @@
+// Snake game from https://skilldrick.github.io/easy6502/ running on the CPU core.
+//
+// The game uses a tiny memory-mapped I/O convention instead of the NES PPU:
+// - 0x0200..0x0600: 32x32 screen, one byte (color index) per pixel
+// - 0x00FF: last pressed key (ASCII code of W/A/S/D)
+// - 0x00FE: random number generator, refreshed before every instruction
+//
+// Run with: cargo run --example snake --features sdl
+//
+// Pass `--capture-session <file>` to record the pressed keys and the RNG seed,
+// and `--replay-session <file>` to play a recorded session back exactly.
+//
+// Pacing between instructions is chosen with `--pacing sleep|busy|none`
+// (default sleep). `busy` spins instead of sleeping, which avoids oversleeping
+// on loaded desktops at the cost of a full core. `--no-vsync` presents frames
+// as soon as they change, independently of the pacing.
+extern crate sdl2;
+
+use nes_emulator::framebuffer::SimpleFramebufferDevice;
+use nes_emulator::prelude::*;
+use nes_emulator::session::Session;
+use std::time::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sdl2::event::Event;
+use sdl2::EventPump;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+// Time the game gets per instruction, it runs far too fast otherwise
+const INSTRUCTION_DELAY: Duration = Duration::from_micros(70);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pacing {
+    Sleep,
+    Busy,
+    None,
+}
+
+impl Pacing {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "sleep" => Ok(Pacing::Sleep),
+            "busy" => Ok(Pacing::Busy),
+            "none" => Ok(Pacing::None),
+            _ => Err(format!("Invalid pacing: {} (expected sleep, busy or none)", value)),
+        }
+    }
+
+    fn wait(self, delay: Duration) {
+        match self {
+            Pacing::Sleep => std::thread::sleep(delay),
+            Pacing::Busy => {
+                let deadline = Instant::now() + delay;
+                while Instant::now() < deadline {
+                    std::hint::spin_loop();
+                }
+            }
+            Pacing::None => {}
+        }
+    }
+}
+
+// Value the game expects at 0x00FF for a key name
+fn key_value(key: &str) -> Option<u8> {
+    match key {
+        "W" => Some(0x77),
+        "S" => Some(0x73),
+        "A" => Some(0x61),
+        "D" => Some(0x64),
+        _ => None,
+    }
+}
+
+// Returns the names of the pressed keys, or None when the user wants to quit
+fn poll_keys(event_pump: &mut EventPump) -> Option<Vec<String>> {
+    let mut keys = vec![];
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                return None;
+            }
+            Event::KeyDown { keycode: Some(keycode), .. } => keys.push(keycode.name()),
+            _ => {/* do nothing */}
+        }
+    }
+    Some(keys)
+}
+
+pub fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let capture_path = arg_value("--capture-session");
+    let replay = match arg_value("--replay-session") {
+        Some(path) => Some(Session::load(&path)?),
+        None => None,
+    };
+    let seed = replay.as_ref().map_or_else(rand::random, |session| session.seed);
+    let mut capture = capture_path.as_ref().map(|_| Session::new(seed));
+    let pacing = match arg_value("--pacing") {
+        Some(value) => Pacing::parse(&value)?,
+        None => Pacing::Sleep,
+    };
+    let vsync = !args.iter().any(|arg| arg == "--no-vsync");
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Snake game", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
+        .position_centered()
+        .build().unwrap();
+ 
+    let mut canvas_builder = window.into_canvas();
+    if vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(10.0, 10.0).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
+    
+    let mut bus = Bus::new(ROM::from_file("roms/snake.nes").unwrap());
+    bus.attach_framebuffer(SimpleFramebufferDevice::new(0x0200, 32, 32));
+    let mut cpu = CPU::new(bus);
+    cpu.reset();
+
+    let mut screen_state = [0u8; 32 * 3 * 32];
+    let mut rng = StdRng::seed_from_u64(seed);
+    let start = Instant::now();
+    let mut step: u64 = 0;
+    let mut next_replay_event = 0;
+
+    let result = cpu.execute_with_callback(move |cpu| {
+        let Some(mut keys) = poll_keys(&mut event_pump) else {
+            if let (Some(session), Some(path)) = (&capture, &capture_path) {
+                session.save(path).unwrap();
+                println!("Session saved to {}", path);
+            }
+            std::process::exit(0)
+        };
+        if let Some(session) = &replay {
+            keys.clear();
+            while let Some(event) = session.events.get(next_replay_event).filter(|e| e.step == step) {
+                keys.push(event.key.clone());
+                next_replay_event += 1;
+            }
+        }
+        for key in keys {
+            if let Some(session) = capture.as_mut() {
+                session.record(step, start.elapsed().as_millis(), &key);
+            }
+            if let Some(value) = key_value(&key) {
+                cpu.write_mem(0xff, value);
+            }
+        }
+        cpu.write_mem(0xfe, rng.gen_range(1..16));
+        step += 1;
+ 
+        let framebuffer = cpu.bus.framebuffer_mut().unwrap();
+        if framebuffer.render_rgb(&mut screen_state) {
+            texture.update(None, &screen_state, 32 * 3).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+        }
+ 
+        pacing.wait(INSTRUCTION_DELAY);
+    });
+    result.map_err(|e| e.to_string())?;
+    Ok(())
+}
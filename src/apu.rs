@@ -0,0 +1,21 @@
+use crate::irq::IrqLine;
+
+// What the Bus needs from an APU, attached with Bus::attach_apu. The APU
+// drives its frame and DMC interrupts on the IRQ line it's given.
+pub trait Apu {
+    // $4000-$4013, $4015 and $4017
+    fn write_register(&mut self, addr: u16, value: u8, irq: &mut IrqLine);
+
+    // $4015 read, clearing the frame interrupt
+    fn read_status(&mut self, irq: &mut IrqLine) -> u8;
+
+    // Side-effect-free $4015 read
+    fn peek_status(&self, irq: &IrqLine) -> u8;
+
+    // Runs for `cycles` CPU cycles. Returns the address of the next DMC
+    // sample byte when the DMC needs one, the CPU then stalls to fetch it
+    fn tick(&mut self, cycles: u16, irq: &mut IrqLine) -> Option<u16>;
+
+    // Sample byte fetched for the DMC
+    fn dmc_sample(&mut self, value: u8);
+}
@@ -1,15 +1,92 @@
-use crate::cpu::Mem;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::apu::Apu;
+use crate::cpu::{Mem, MemAccess};
+use crate::framebuffer::SimpleFramebufferDevice;
+use crate::irq::{IrqLine, IrqSource};
+use crate::ppu::Ppu;
 use crate::rom::ROM;
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
 const ROM_START_IN_MEMORY: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x4000;
+
+const PPU_REGISTER_NAMES: [&str; 8] = [
+    "PPUCTRL", "PPUMASK", "PPUSTATUS", "OAMADDR", "OAMDATA", "PPUSCROLL", "PPUADDR", "PPUDATA",
+];
+const APU_IO_REGISTER_NAMES: [&str; 0x18] = [
+    "SQ1_VOL", "SQ1_SWEEP", "SQ1_LO", "SQ1_HI", "SQ2_VOL", "SQ2_SWEEP", "SQ2_LO", "SQ2_HI",
+    "TRI_LINEAR", "APU_UNUSED", "TRI_LO", "TRI_HI", "NOISE_VOL", "APU_UNUSED", "NOISE_LO", "NOISE_HI",
+    "DMC_FREQ", "DMC_RAW", "DMC_START", "DMC_LEN", "OAMDMA", "SND_CHN", "JOY1", "JOY2",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ram,
+    PpuRegisters,
+    ApuIo,
+    Framebuffer,
+    PrgRom,
+    Unmapped,
+}
+
+pub const REGIONS: [Region; 6] = [
+    Region::Ram,
+    Region::PpuRegisters,
+    Region::ApuIo,
+    Region::Framebuffer,
+    Region::PrgRom,
+    Region::Unmapped,
+];
+
+// CPU memory traffic per region. Accesses are one byte each, so the counts
+// are also byte counts. Frontends reset them every frame for per-frame numbers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BusStats {
+    reads: [u64; 6],
+    writes: [u64; 6],
+}
+
+impl BusStats {
+    pub fn reads(&self, region: Region) -> u64 {
+        self.reads[region as usize]
+    }
+
+    pub fn writes(&self, region: Region) -> u64 {
+        self.writes[region as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.reads.iter().chain(&self.writes).sum()
+    }
+}
 
 pub struct Bus {
     cpu_vram: [u8; 0xFFFF],
     rom: Option<ROM>,
+    irq: IrqLine,
+    nmi: bool,
+    // Source page of an OAM DMA requested by a $4014 write
+    oam_dma: Option<u8>,
+    // Address the DMC wants its next sample byte from, then the byte fetched
+    dmc_dma: Option<u16>,
+    dmc_sample: Option<u8>,
+    framebuffer: Option<SimpleFramebufferDevice>,
+    ppu: Option<Box<dyn Ppu>>,
+    apu: Option<Box<dyn Apu>>,
+    stats: BusStats,
+    // 64KB of plain RAM replacing the NES memory map, for CPU test suites
+    flat_memory: Option<Vec<u8>>,
+    // Last value the CPU read or wrote, what unmapped reads return
+    open_bus: u8,
 }
 
 impl Bus {
@@ -17,62 +94,586 @@ impl Bus {
         Self {
             cpu_vram: [0; 0xFFFF],
             rom: Some(rom),
+            irq: IrqLine::new(),
+            nmi: false,
+            oam_dma: None,
+            dmc_dma: None,
+            dmc_sample: None,
+            framebuffer: None,
+            ppu: None,
+            apu: None,
+            stats: BusStats::default(),
+            flat_memory: None,
+            open_bus: 0,
+        }
+    }
+
+    // No mirroring, devices or ROM: every address is a RAM byte
+    pub fn flat() -> Self {
+        Self {
+            flat_memory: Some(vec![0; 0x10000]),
+            ..Self::new(ROM::empty())
         }
     }
 
     pub fn load_rom(&mut self, rom: ROM) {
         self.rom = Some(rom);
     }
+
+    pub fn rom(&self) -> Option<&ROM> {
+        self.rom.as_ref()
+    }
+
+    // The value left on the data bus by the last CPU access
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    pub fn attach_framebuffer(&mut self, framebuffer: SimpleFramebufferDevice) {
+        self.framebuffer = Some(framebuffer);
+    }
+
+    pub fn framebuffer_mut(&mut self) -> Option<&mut SimpleFramebufferDevice> {
+        self.framebuffer.as_mut()
+    }
+
+    pub fn attach_ppu(&mut self, ppu: Box<dyn Ppu>) {
+        self.ppu = Some(ppu);
+    }
+
+    pub fn attach_apu(&mut self, apu: Box<dyn Apu>) {
+        self.apu = Some(apu);
+    }
+
+    // Lets the PPU and APU catch up with the CPU after an instruction
+    pub fn tick(&mut self, cycles: u16) {
+        if self.ppu.as_mut().is_some_and(|ppu| ppu.tick(cycles)) {
+            self.trigger_nmi();
+        }
+        if let Some(addr) = self.apu.as_mut().and_then(|apu| apu.tick(cycles, &mut self.irq)) {
+            self.request_dmc_dma(addr);
+        }
+    }
+
+    pub fn region(&self, addr: u16) -> Region {
+        if self.framebuffer.as_ref().is_some_and(|fb| fb.contains(addr)) {
+            return Region::Framebuffer;
+        }
+        match addr {
+            RAM ..= RAM_MIRRORS_END => Region::Ram,
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => Region::PpuRegisters,
+            0x4000 ..= 0x4017 => Region::ApuIo,
+            ROM_START_IN_MEMORY ..= 0xFFFF => Region::PrgRom,
+            _ => Region::Unmapped,
+        }
+    }
+
+    // Whether a write there reaches something: PPU registers need a PPU and
+    // PRG space needs PRG ROM. Debuggers check it before poking
+    pub fn is_writable(&self, addr: u16) -> bool {
+        match self.region(addr) {
+            Region::PpuRegisters => self.ppu.is_some(),
+            Region::PrgRom => self.rom.as_ref().is_some_and(|rom| !rom.prg_rom.is_empty()),
+            _ => true,
+        }
+    }
+
+    pub fn stats(&self) -> &BusStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = BusStats::default();
+    }
+
+    // Mapper query: the 16KB PRG bank currently mapped at a CPU address and
+    // the offset in it, None outside PRG space
+    pub fn prg_bank(&self, addr: u16) -> Option<(usize, u16)> {
+        if addr < ROM_START_IN_MEMORY {
+            return None;
+        }
+        let mut offset = (addr - ROM_START_IN_MEMORY) as usize;
+        if let Some(rom) = self.rom.as_ref().filter(|rom| !rom.prg_rom.is_empty()) {
+            offset %= rom.prg_rom.len();
+        }
+        Some((offset / PRG_BANK_SIZE, (offset % PRG_BANK_SIZE) as u16))
+    }
+
+    // Human readable name of the device behind an address, for trace logs
+    pub fn describe_address(&self, addr: u16) -> String {
+        if self.framebuffer.as_ref().is_some_and(|fb| fb.contains(addr)) {
+            return format!("${:04X} framebuffer", addr);
+        }
+        match addr {
+            RAM ..= RAM_MIRRORS_END => format!("${:04X} RAM ${:04X}", addr, addr & 0x07FF),
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                format!("${:04X} {}", addr, PPU_REGISTER_NAMES[(addr & 0x0007) as usize])
+            }
+            0x4000 ..= 0x4017 => {
+                format!("${:04X} {}", addr, APU_IO_REGISTER_NAMES[(addr - 0x4000) as usize])
+            }
+            ROM_START_IN_MEMORY ..= 0xFFFF => {
+                let (bank, offset) = self.prg_bank(addr).unwrap_or_default();
+                format!("${:04X} PRG bank {} offset ${:04X}", addr, bank, offset)
+            }
+            _ => format!("${:04X} unmapped", addr),
+        }
+    }
+
+    // The readable device registers with their current values, peeked so the
+    // debugger can show them without disturbing the emulation
+    pub fn peek_io_registers(&self) -> Vec<(u16, u8)> {
+        let ppu_registers = [0x2002, 0x2004, 0x2007].into_iter().filter(|_| self.ppu.is_some());
+        ppu_registers
+            .chain([APU_STATUS])
+            .map(|addr| (addr, self.peek_mem(addr)))
+            .collect()
+    }
+
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq.assert(source);
+    }
+
+    pub fn acknowledge_irq(&mut self, source: IrqSource) {
+        self.irq.acknowledge(source);
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq.is_asserted()
+    }
+
+    pub fn irq_sources(&self) -> Vec<IrqSource> {
+        self.irq.asserting_sources()
+    }
+
+    // NMI is edge-triggered: a falling edge latches it until the CPU services it
+    pub fn trigger_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi
+    }
+
+    // Returns whether an NMI was latched, clearing the latch
+    pub fn take_nmi(&mut self) -> bool {
+        core::mem::take(&mut self.nmi)
+    }
+
+    // Returns the page of a requested OAM DMA, clearing the request. The CPU
+    // stalls for the transfer, the page was copied to the PPU on the write
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+
+    // RDY line for the DMC: the CPU halts after the current instruction to
+    // let the DMA unit fetch the sample byte at `addr`
+    pub fn request_dmc_dma(&mut self, addr: u16) {
+        self.dmc_dma = Some(addr);
+    }
+
+    pub fn take_dmc_dma(&mut self) -> Option<u16> {
+        self.dmc_dma.take()
+    }
+
+    pub fn complete_dmc_dma(&mut self, value: u8) {
+        match self.apu.as_mut() {
+            Some(apu) => apu.dmc_sample(value),
+            None => self.dmc_sample = Some(value),
+        }
+    }
+
+    // The sample byte fetched for the DMC without an APU attached, if one
+    // arrived since the last call
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    // $4015 read without an APU: DMC and frame IRQ flags in bits 7-6, the
+    // length counter bits are always clear
+    fn apu_status(&self) -> u8 {
+        if let Some(apu) = &self.apu {
+            return apu.peek_status(&self.irq);
+        }
+        let mut status = 0;
+        if self.irq.is_asserted_by(IrqSource::Dmc) {
+            status |= 0b1000_0000;
+        }
+        if self.irq.is_asserted_by(IrqSource::ApuFrame) {
+            status |= 0b0100_0000;
+        }
+        status
+    }
+
+    // A CPU read: registers with read side effects go to their device
+    fn read_device(&mut self, addr: u16) -> u8 {
+        if self.flat_memory.is_none() && self.framebuffer.as_ref().is_none_or(|fb| !fb.contains(addr)) {
+            if let (PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END, Some(ppu)) = (addr, self.ppu.as_mut()) {
+                return ppu.read_register((addr & 0x0007) as u8);
+            }
+            if let (APU_STATUS, Some(apu)) = (addr, self.apu.as_mut()) {
+                return apu.read_status(&mut self.irq);
+            }
+        }
+        let value = self.read_mem(addr);
+        if addr == APU_STATUS && self.flat_memory.is_none() {
+            // Any read, dummy ones included, clears the frame interrupt flag
+            self.irq.acknowledge(IrqSource::ApuFrame);
+        }
+        value
+    }
 }
 
 impl Mem for Bus {
     fn read_mem(&self, addr: u16) -> u8 {
+        if let Some(memory) = &self.flat_memory {
+            return memory[addr as usize];
+        }
+        if let Some(framebuffer) = self.framebuffer.as_ref().filter(|fb| fb.contains(addr)) {
+            return framebuffer.read_mem(addr);
+        }
         match addr {
             RAM ..= RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x07FF;
                 self.cpu_vram[mirror_down_addr as usize]
             }
-            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0x2007;
-                println!("PPU register read at {:#X}", addr);
-                todo!("PPU is not supported yet - read")
-            }
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => match &self.ppu {
+                Some(ppu) => ppu.peek_register((addr & 0x0007) as u8),
+                None => {
+                    log!("PPU register read at {:#X}", addr);
+                    todo!("PPU is not supported yet - read")
+                }
+            },
+            APU_STATUS => self.apu_status(),
             0x8000 ..= 0xFFFF => {
                 let rom = self.rom.as_ref().unwrap();
-                let mut addr = addr - 0x8000;
-
-                // Mirroring for 16KB PRG ROM
-                if rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-                    addr = addr % 0x4000;
-                }
-                rom.prg_rom[addr as usize]
+                rom.prg_rom[prg_rom_offset(rom, addr)]
             }
             _ => {
-                println!("Ignoring mem access at {:#X}", addr);
-                0
+                log!("Open bus read at {:#X}", addr);
+                self.open_bus
             }
         }
     }
 
+    fn peek_mem(&self, addr: u16) -> u8 {
+        if let Some(memory) = &self.flat_memory {
+            return memory[addr as usize];
+        }
+        if let Some(framebuffer) = self.framebuffer.as_ref().filter(|fb| fb.contains(addr)) {
+            return framebuffer.peek_mem(addr);
+        }
+        match addr {
+            RAM ..= RAM_MIRRORS_END | APU_STATUS | ROM_START_IN_MEMORY ..= 0xFFFF => self.read_mem(addr),
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END if self.ppu.is_some() => self.read_mem(addr),
+            0x4018 ..= 0x7FFF => self.open_bus,
+            // Device registers can't be peeked yet
+            _ => 0,
+        }
+    }
+
+    fn read_mem_access(&mut self, addr: u16, _access: MemAccess) -> u8 {
+        self.stats.reads[self.region(addr) as usize] += 1;
+        self.open_bus = self.read_device(addr);
+        self.open_bus
+    }
+
+    fn write_mem_access(&mut self, addr: u16, data: u8, _access: MemAccess) {
+        self.stats.writes[self.region(addr) as usize] += 1;
+        self.open_bus = data;
+        self.write_mem(addr, data);
+    }
+
     fn write_mem(&mut self, addr: u16, data: u8) {
+        if let Some(memory) = &mut self.flat_memory {
+            memory[addr as usize] = data;
+            return;
+        }
+        if let Some(framebuffer) = self.framebuffer.as_mut().filter(|fb| fb.contains(addr)) {
+            framebuffer.write_mem(addr, data);
+            return;
+        }
         match addr {
             RAM ..= RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x07FF;
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
-            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0x2007;
-                println!("PPU register write at {:#X}", addr);
-                todo!("PPU is not supported yet - write")
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => match self.ppu.as_mut() {
+                Some(ppu) => ppu.write_register((addr & 0x0007) as u8, data),
+                None => {
+                    log!("PPU register write at {:#X}", addr);
+                    todo!("PPU is not supported yet - write")
+                }
+            },
+            OAM_DMA => {
+                if self.ppu.is_some() {
+                    let page = (data as u16) << 8;
+                    let oam: [u8; 256] = core::array::from_fn(|i| self.read_mem(page | i as u16));
+                    if let Some(ppu) = self.ppu.as_mut() {
+                        ppu.write_oam(&oam);
+                    }
+                }
+                self.oam_dma = Some(data);
+            }
+            0x4000 ..= 0x4013 | APU_STATUS | 0x4017 if self.apu.is_some() => {
+                self.apu.as_mut().unwrap().write_register(addr, data, &mut self.irq);
+            }
+            APU_STATUS => {
+                // Channel enables are ignored without an APU, but the write
+                // still clears the DMC interrupt flag
+                self.irq.acknowledge(IrqSource::Dmc);
             }
             ROM_START_IN_MEMORY ..= 0xFFFF => {
                 // TODO: Add unsafe mode to explicitly allow writing to ROM
                 // panic!("Write to ROM at {:#X}: {:#X}", addr, data);
-                self.rom.as_mut().unwrap().prg_rom[(addr - ROM_START_IN_MEMORY) as usize] = data;
+                let rom = self.rom.as_mut().unwrap();
+                let offset = prg_rom_offset(rom, addr);
+                rom.prg_rom[offset] = data;
             }
             _ => {
-                println!("Ignoring mem write-access at {:#X}: {:#X}", addr, data);
+                log!("Ignoring mem write-access at {:#X}: {:#X}", addr, data);
             }
         }
     }
-}
\ No newline at end of file
+
+    fn tick(&mut self, cycles: u16) {
+        Bus::tick(self, cycles)
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        Bus::take_nmi(self)
+    }
+
+    fn irq_pending(&self) -> bool {
+        Bus::irq_pending(self)
+    }
+
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        Bus::take_oam_dma(self)
+    }
+
+    fn take_dmc_dma(&mut self) -> Option<u16> {
+        Bus::take_dmc_dma(self)
+    }
+
+    fn complete_dmc_dma(&mut self, value: u8) {
+        Bus::complete_dmc_dma(self, value)
+    }
+
+    fn describe_address(&self, addr: u16) -> String {
+        Bus::describe_address(self, addr)
+    }
+}
+
+// Offset of a $8000-$FFFF address in PRG ROM, 16KB carts being mirrored at $C000
+fn prg_rom_offset(rom: &ROM, addr: u16) -> usize {
+    let offset = (addr - ROM_START_IN_MEMORY) as usize;
+    if rom.prg_rom.len() == 0x4000 {
+        offset % 0x4000
+    } else {
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_writable() {
+        let mut bus = Bus::new(ROM::empty());
+        assert!(bus.is_writable(0x0010));
+        assert!(bus.is_writable(0x4015));
+        assert!(!bus.is_writable(0x2000));
+        bus.attach_ppu(Box::new(TestPpu::default()));
+        assert!(bus.is_writable(0x3FF8));
+    }
+
+    #[test]
+    fn test_write_mirrored_prg_rom() {
+        let mut rom = ROM::empty();
+        rom.prg_rom = vec![0; 0x4000];
+        let mut bus = Bus::new(rom);
+        bus.write_mem(0xC001, 0xEA);
+        assert_eq!(bus.rom().unwrap().prg_rom[1], 0xEA);
+        assert_eq!(bus.read_mem(0x8001), 0xEA);
+    }
+
+    #[test]
+    fn test_describe_address() {
+        let mut rom = ROM::empty();
+        rom.prg_rom = vec![0; 0x4000];
+        let mut bus = Bus::new(rom);
+        bus.attach_framebuffer(SimpleFramebufferDevice::new(0x0200, 32, 32));
+
+        assert_eq!(bus.describe_address(0x0010), "$0010 RAM $0010");
+        assert_eq!(bus.describe_address(0x0810), "$0810 RAM $0010");
+        assert_eq!(bus.describe_address(0x0200), "$0200 framebuffer");
+        assert_eq!(bus.describe_address(0x3FFA), "$3FFA PPUSTATUS");
+        assert_eq!(bus.describe_address(0x4016), "$4016 JOY1");
+        assert_eq!(bus.describe_address(0x5000), "$5000 unmapped");
+        // 16KB carts are mirrored at $C000
+        assert_eq!(bus.describe_address(0xDF20), "$DF20 PRG bank 0 offset $1F20");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.write_mem_access(0x0010, 1, MemAccess::Write);
+        bus.read_mem_access(0x0810, MemAccess::Read);
+        bus.read_mem_access(0x8000, MemAccess::Fetch);
+        bus.read_mem_access(APU_STATUS, MemAccess::Read);
+        // Direct accesses (loaders, debugger pokes) are not CPU traffic
+        bus.write_mem(0x0010, 2);
+
+        assert_eq!(bus.stats().reads(Region::Ram), 1);
+        assert_eq!(bus.stats().writes(Region::Ram), 1);
+        assert_eq!(bus.stats().reads(Region::PrgRom), 1);
+        assert_eq!(bus.stats().reads(Region::ApuIo), 1);
+        assert_eq!(bus.stats().total(), 4);
+        bus.reset_stats();
+        assert_eq!(bus.stats().total(), 0);
+    }
+
+    #[test]
+    fn test_apu_status() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.assert_irq(IrqSource::ApuFrame);
+        bus.assert_irq(IrqSource::Dmc);
+
+        assert_eq!(bus.peek_mem(APU_STATUS), 0b1100_0000);
+        assert!(bus.irq_pending());
+        // Reading clears the frame interrupt only
+        assert_eq!(bus.read_mem_access(APU_STATUS, MemAccess::Read), 0b1100_0000);
+        assert_eq!(bus.read_mem_access(APU_STATUS, MemAccess::Read), 0b1000_0000);
+        // Writing clears the DMC interrupt
+        bus.write_mem(APU_STATUS, 0x1F);
+        assert_eq!(bus.peek_mem(APU_STATUS), 0);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.write_mem(0x0010, 0x42);
+        assert_eq!(bus.read_mem_access(0x5000, MemAccess::Read), 0);
+        assert_eq!(bus.read_mem_access(0x0010, MemAccess::Read), 0x42);
+        assert_eq!(bus.read_mem_access(0x5000, MemAccess::Read), 0x42);
+        assert_eq!(bus.peek_mem(0x6000), 0x42);
+        bus.write_mem_access(0x0000, 0x17, MemAccess::Write);
+        assert_eq!(bus.read_mem_access(0x6000, MemAccess::Read), 0x17);
+        assert_eq!(bus.open_bus(), 0x17);
+    }
+
+    #[test]
+    fn test_flat() {
+        let mut bus = Bus::flat();
+        bus.write_mem(0x0800, 1);
+        bus.write_mem(0x2000, 2);
+        bus.write_mem(0xFFFF, 3);
+        assert_eq!(bus.read_mem(0x0000), 0);
+        assert_eq!(bus.read_mem(0x0800), 1);
+        assert_eq!(bus.read_mem(0x2000), 2);
+        assert_eq!(bus.peek_mem(0xFFFF), 3);
+    }
+
+    // Registers in an array, NMI on every tick with $2000 bit 7 set. OAMDATA
+    // reads the first OAM byte
+    #[derive(Default)]
+    struct TestPpu {
+        registers: [u8; 8],
+    }
+
+    impl Ppu for TestPpu {
+        fn read_register(&mut self, register: u8) -> u8 {
+            core::mem::take(&mut self.registers[register as usize])
+        }
+
+        fn peek_register(&self, register: u8) -> u8 {
+            self.registers[register as usize]
+        }
+
+        fn write_register(&mut self, register: u8, value: u8) {
+            self.registers[register as usize] = value;
+        }
+
+        fn write_oam(&mut self, data: &[u8; 256]) {
+            self.registers[4] = data[0];
+        }
+
+        fn tick(&mut self, _cycles: u16) -> bool {
+            self.registers[0] & 0x80 != 0
+        }
+    }
+
+    // Raises the frame interrupt on $4017 writes, asks for a sample every tick
+    struct TestApu;
+
+    impl Apu for TestApu {
+        fn write_register(&mut self, addr: u16, _value: u8, irq: &mut IrqLine) {
+            if addr == 0x4017 {
+                irq.assert(IrqSource::ApuFrame);
+            }
+        }
+
+        fn read_status(&mut self, irq: &mut IrqLine) -> u8 {
+            let status = self.peek_status(irq);
+            irq.acknowledge(IrqSource::ApuFrame);
+            status
+        }
+
+        fn peek_status(&self, irq: &IrqLine) -> u8 {
+            if irq.is_asserted_by(IrqSource::ApuFrame) { 0x40 } else { 0x01 }
+        }
+
+        fn tick(&mut self, _cycles: u16, _irq: &mut IrqLine) -> Option<u16> {
+            Some(0xC000)
+        }
+
+        fn dmc_sample(&mut self, _value: u8) {}
+    }
+
+    #[test]
+    fn test_attached_devices() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.attach_ppu(Box::new(TestPpu::default()));
+        bus.attach_apu(Box::new(TestApu));
+
+        // $2008 mirrors $2000
+        bus.write_mem(0x2008, 0x80);
+        assert_eq!(bus.peek_mem(0x2000), 0x80);
+        bus.tick(1);
+        assert!(bus.take_nmi());
+        assert_eq!(bus.take_dmc_dma(), Some(0xC000));
+        assert_eq!(bus.read_mem_access(0x2000, MemAccess::Read), 0x80);
+        assert_eq!(bus.peek_mem(0x2000), 0);
+
+        bus.write_mem(0x0300, 0x42);
+        bus.write_mem(OAM_DMA, 0x03);
+        assert_eq!(bus.take_oam_dma(), Some(0x03));
+        assert_eq!(bus.peek_mem(0x2004), 0x42);
+
+        bus.write_mem(0x4017, 0);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.read_mem_access(APU_STATUS, MemAccess::Read), 0x40);
+        assert!(!bus.irq_pending());
+        assert_eq!(bus.peek_mem(APU_STATUS), 0x01);
+    }
+
+    #[test]
+    fn test_peek_io_registers() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.write_mem(0x4017, 0);
+        assert_eq!(bus.peek_io_registers(), vec![(APU_STATUS, 0)]);
+
+        bus.attach_ppu(Box::new(TestPpu::default()));
+        bus.attach_apu(Box::new(TestApu));
+        bus.write_mem(0x2002, 0x80);
+        bus.write_mem(0x4017, 0);
+        let registers = vec![(0x2002, 0x80), (0x2004, 0), (0x2007, 0), (APU_STATUS, 0x40)];
+        assert_eq!(bus.peek_io_registers(), registers);
+        // Peeking left PPUSTATUS and the frame interrupt alone
+        assert_eq!(bus.peek_io_registers(), registers);
+        assert!(bus.irq_pending());
+    }
+}
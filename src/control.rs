@@ -0,0 +1,88 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const RUNNING: u8 = 0;
+const PAUSED: u8 = 1;
+const STOPPING: u8 = 2;
+
+// Lets another thread, e.g. a GUI, stop or pause CPU::execute() between two
+// instructions. Clones share the same state, get one with CPU::control()
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionControl {
+    state: Arc<AtomicU8>,
+}
+
+impl ExecutionControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // execute() returns StopReason::Stopped before its next instruction. The
+    // request is consumed, the following execute() runs normally
+    pub fn stop(&self) {
+        self.state.store(STOPPING, Ordering::SeqCst);
+    }
+
+    // execute() waits before its next instruction until resume() or stop()
+    pub fn pause(&self) {
+        let _ = self.state.compare_exchange(RUNNING, PAUSED, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.state.compare_exchange(PAUSED, RUNNING, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == PAUSED
+    }
+
+    // Checked by the execute loop: sits out a pause, then takes a stop request
+    pub(crate) fn should_stop(&self) -> bool {
+        while self.is_paused() {
+            wait();
+        }
+        self.state
+            .compare_exchange(STOPPING, RUNNING, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+}
+
+#[cfg(feature = "std")]
+fn wait() {
+    std::thread::sleep(std::time::Duration::from_millis(1));
+}
+
+#[cfg(not(feature = "std"))]
+fn wait() {
+    core::hint::spin_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control() {
+        let control = ExecutionControl::new();
+        assert!(!control.should_stop());
+        control.pause();
+        assert!(control.clone().is_paused());
+        control.resume();
+        assert!(!control.should_stop());
+
+        control.stop();
+        // Pausing doesn't cancel a stop, and the stop is taken only once
+        control.pause();
+        assert!(control.should_stop());
+        assert!(!control.should_stop());
+
+        control.pause();
+        let resumer = control.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            resumer.resume();
+        });
+        assert!(!control.should_stop());
+        thread.join().unwrap();
+    }
+}
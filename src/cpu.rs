@@ -1,24 +1,360 @@
-use std::collections::HashMap;
-use std::ops::{BitAnd, BitOr, BitXor};
-
-use crate::opcodes::{self, OpCode};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitXor};
+
+use crate::control::ExecutionControl;
+use crate::opcodes::{self, Instruction, OpCode};
+use crate::profiler::Profile;
+use crate::scheduler::Scheduler;
 use crate::status_flags::{ProcessorStatus, StatusFlag};
 use crate::bus::Bus;
 
 const STACK: u16 = 0x100;
+// PPU and APU/IO registers: writes there aren't undone by step_back()
+const IO_REGISTERS: core::ops::RangeInclusive<u16> = 0x2000..=0x401F;
 pub const STACK_RESET: u8 = 0xFF;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+pub const HISTORY_SIZE: usize = 200;
+const INTERRUPT_CYCLES: u16 = 7;
+// Plus one when the DMA starts on an odd cycle
+const OAM_DMA_CYCLES: u16 = 513;
+// A DMC fetch halts the CPU for up to 4 cycles, 2 when it lands in an OAM DMA
+const DMC_DMA_CYCLES: u16 = 4;
+const DMC_DMA_CYCLES_DURING_OAM_DMA: u16 = 2;
+
+// Effects landing a while after the instruction causing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CpuEvent {
+    // The I flag the next interrupt poll sees, after CLI, SEI or PLP changed it
+    InterruptPollFlag(bool),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CpuError {
+    UnknownOpcode { opcode: u8, address: u16 },
+    // A KIL opcode at `address` stopped the CPU until the next reset
+    Jammed { address: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, address } => {
+                write!(f, "Unknown opcode {:#04x} at {:#06X}", opcode, address)
+            }
+            CpuError::Jammed { address } => write!(f, "CPU jammed at {:#06X}", address),
+        }
+    }
+}
+
+impl core::error::Error for CpuError {}
+
+// Why execute() returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Brk,
+    // PC reached a breakpoint, the instruction there hasn't run yet
+    Breakpoint(u16),
+    // The last instruction touched a watched address
+    Watchpoint(WatchHit),
+    // ExecutionControl::stop() was called
+    Stopped,
+    // The run_until() predicate returned true
+    Condition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// Stops execution after an instruction reads and/or writes an address in
+// `start..=end`. Opcode fetches don't count, breakpoints are for that
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u16, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::ReadWrite => true,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+// "$2002 read", "$0200..$02FF write"
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04X}", self.start)?;
+        if self.end != self.start {
+            write!(f, "..${:04X}", self.end)?;
+        }
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::ReadWrite => "read/write",
+        };
+        write!(f, " {}", kind)
+    }
+}
+
+// First watched access of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    // Address of the instruction that made the access
+    pub program_counter: u16,
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = if self.write { "Write" } else { "Read" };
+        write!(
+            f,
+            "{} ${:02X} at ${:04X} by the instruction at ${:04X}",
+            verb, self.value, self.address, self.program_counter
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Subroutine,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+// A JSR or an interrupt that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    // Subroutine or interrupt handler address
+    pub entry: u16,
+    // Where execution resumes after RTS/RTI
+    pub return_address: u16,
+    // Stack pointer before the call pushed anything, and again after the return
+    pub stack_pointer: u8,
+}
+
+// "JSR $C123, returns to $8008"
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            CallKind::Subroutine => "JSR",
+            CallKind::Nmi => "NMI",
+            CallKind::Irq => "IRQ",
+            CallKind::Brk => "BRK",
+        };
+        write!(f, "{} ${:04X}, returns to ${:04X}", kind, self.entry, self.return_address)
+    }
+}
+
+// CPU state right before an instruction is executed
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    pub program_counter: u16,
+    pub opcode: u8,
+    pub operands: [u8; 2],
+    pub register_accumulator: u8,
+    pub index_register_x: u8,
+    pub index_register_y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+}
+
+// "$8000  A9 42     LDA  A:00 X:00 Y:00 P:nv-bdIzc SP:FD"
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcode = opcodes::CPU_OPCODE_TABLE[self.opcode as usize];
+        let bytes = opcode.map_or(1, |op| op.bytes as usize);
+        let mut code = format!("{:02X}", self.opcode);
+        for operand in &self.operands[..bytes - 1] {
+            code.push_str(&format!(" {:02X}", operand));
+        }
+        write!(
+            f,
+            "${:04X}  {:<8}  {:<4} A:{:02X} X:{:02X} Y:{:02X} P:{} SP:{:02X}",
+            self.program_counter,
+            code,
+            opcode.map_or("???", |op| op.label),
+            self.register_accumulator,
+            self.index_register_x,
+            self.index_register_y,
+            ProcessorStatus { status: self.status },
+            self.stack_pointer
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    // After the opcode is fetched, before the instruction runs
+    Before,
+    // Once the instruction has run
+    After,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HookId(u32);
+
+// What an instruction hook is told about the instruction
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionEvent {
+    pub opcode: &'static OpCode,
+    // Where the instruction is
+    pub address: u16,
+    // Memory the operand refers to, None for modes that don't address memory
+    pub operand_address: Option<u16>,
+    // Registers before the instruction for Before hooks, after it for After
+    pub state: CpuState,
+}
+
+type Hook<M> = Box<dyn FnMut(&mut CPU<M>, &InstructionEvent)>;
+
+// What power_on() and reset() leave in RAM and the registers. The default is
+// the clean state test programs expect: RAM untouched, SP=$FF, I clear
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PowerOnConfig {
+    // Seed to fill the 2KB of internal RAM with at power-on, real RAM powers
+    // up with garbage
+    pub random_ram: Option<u64>,
+    // Reset runs the interrupt sequence with the writes suppressed: SP goes
+    // down by 3 instead of being set, ending at $FD after power-on
+    pub hardware_stack_pointer: bool,
+    pub interrupt_disable: bool,
+}
+
+impl PowerOnConfig {
+    // A real 2A03: random RAM from `seed`, SP decremented, I set
+    pub fn hardware(seed: u64) -> Self {
+        Self {
+            random_ram: Some(seed),
+            hardware_stack_pointer: true,
+            interrupt_disable: true,
+        }
+    }
+}
+
+// SplitMix64, enough to fill RAM reproducibly without pulling in a crate
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Everything the CPU itself holds, without the bus, to save and restore it
+// exactly: registers, cycle counters and the pending interrupt polling quirks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub register_accumulator: u8,
+    pub index_register_x: u8,
+    pub index_register_y: u8,
+    pub status: u8,
+    pub cycles: u64,
+    pub stall_cycles: u16,
+    pub jammed: bool,
+    pub delayed_interrupt_disable: Option<bool>,
+    pub skip_interrupt_poll: bool,
+}
+
+// The registers and cycle count, for debugger frontends to display:
+// "PC:8000 A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub cycles: u64,
+}
 
-pub struct CPU {
+impl fmt::Display for CpuSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc, self.a, self.x, self.y, self.p, self.sp, self.cycles
+        )
+    }
+}
+
+pub struct CPU<M: Mem = Bus> {
     pub program_counter: u16,
     pub stack_pointer: u8,
     pub register_accumulator: u8,
     pub index_register_x: u8,
     pub index_register_y: u8,
     pub status: ProcessorStatus,
-    pub bus: Bus,
+    pub bus: M,
+    // CPU cycles run since power-up or the last reset_cycles()
+    pub cycles: u64,
+    // Cycles the CPU sits out before its next instruction, e.g. for DMA
+    stall_cycles: u16,
+    jammed: bool,
+    history: VecDeque<TraceEntry>,
+    trace: bool,
+    halt_on_brk: bool,
+    cycle_accurate: bool,
+    decimal_mode: bool,
+    power_on: PowerOnConfig,
+    profile: Option<Profile>,
+    // Interrupts are polled before an instruction's last cycle: after CLI,
+    // SEI and PLP the next poll still sees the old I flag, and a taken branch
+    // staying in its page doesn't poll at all
+    events: Scheduler<CpuEvent>,
+    skip_interrupt_poll: bool,
+    // Set when the current instruction's indexed address crossed a page
+    page_crossed: bool,
+    // Cycles a taken branch adds to the current instruction
+    branch_cycles: u16,
+    // Set when the last instruction was a BRK in halt mode
+    halted_on_brk: bool,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    // Shadow of the JSRs and interrupts on the stack, innermost last
+    call_stack: Vec<CallFrame>,
+    control: ExecutionControl,
+    hooks: Vec<(HookId, HookPoint, Hook<M>)>,
+    next_hook_id: u32,
+    // What step_back() needs to undo the last instructions, newest last
+    undo: VecDeque<UndoEntry>,
+    undo_depth: usize,
 }
 
-#[derive(Debug)]
+// The CPU before one step, and the old value of every byte it wrote, in
+// write order
+struct UndoEntry {
+    state: CpuState,
+    call_stack: Vec<CallFrame>,
+    writes: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -33,9 +369,31 @@ pub enum AddressingMode {
     NoneAddressing,
 }
 
+// What a memory access is used for, so devices can tell apart e.g. an opcode
+// fetch from a data read, or the dummy write of a read-modify-write instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemAccess {
+    Fetch,
+    Read,
+    DummyRead,
+    Write,
+    DummyWrite,
+    // DMC sample fetch, made by the DMA unit while the CPU is halted
+    Dma,
+}
+
 pub trait Mem {
     fn read_mem(&self, addr: u16) -> u8;
 
+    fn read_mem_access(&mut self, addr: u16, _access: MemAccess) -> u8 {
+        self.read_mem(addr)
+    }
+
+    // Side-effect-free read for debuggers, tracers and disassemblers
+    fn peek_mem(&self, addr: u16) -> u8 {
+        self.read_mem(addr)
+    }
+
     fn read_mem_u16(&self, addr: u16) -> u16 {
         // Reading 2 bytes in little endian
         let little = self.read_mem(addr);
@@ -48,13 +406,44 @@ pub trait Mem {
     fn write_mem_u16(&mut self, addr: u16, value: u16) {
         // Writing 2 bytes in little endian
         let bytes = u16::to_le_bytes(value);
-        for i in 0..bytes.len() {
-            self.write_mem(addr + i as u16, bytes[i])
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_mem(addr + i as u16, *byte)
         }
     }
+
+    fn write_mem_access(&mut self, addr: u16, value: u8, _access: MemAccess) {
+        self.write_mem(addr, value)
+    }
+
+    // Signals a bus gives the CPU. A plain memory has no devices, so by
+    // default no time passes, no interrupt fires and no DMA is requested
+
+    fn tick(&mut self, _cycles: u16) {}
+
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn take_dmc_dma(&mut self) -> Option<u16> {
+        None
+    }
+
+    fn complete_dmc_dma(&mut self, _value: u8) {}
+
+    fn describe_address(&self, addr: u16) -> String {
+        format!("${:04X}", addr)
+    }
 }
 
-impl Mem for CPU {
+impl<M: Mem> Mem for CPU<M> {
     fn read_mem(&self, addr: u16) -> u8 {
         self.bus.read_mem(addr)
     }
@@ -70,10 +459,33 @@ impl Mem for CPU {
     fn write_mem_u16(&mut self, addr: u16, value: u16) {
         self.bus.write_mem_u16(addr, value);
     }
+
+    fn read_mem_access(&mut self, addr: u16, access: MemAccess) -> u8 {
+        let value = self.bus.read_mem_access(addr, access);
+        if !matches!(access, MemAccess::Fetch | MemAccess::Dma) {
+            self.check_watchpoints(addr, value, false);
+        }
+        value
+    }
+
+    fn peek_mem(&self, addr: u16) -> u8 {
+        self.bus.peek_mem(addr)
+    }
+
+    fn write_mem_access(&mut self, addr: u16, value: u8, access: MemAccess) {
+        if self.undo_depth > 0 && !IO_REGISTERS.contains(&addr) {
+            let old = self.bus.peek_mem(addr);
+            if let Some(entry) = self.undo.back_mut() {
+                entry.writes.push((addr, old));
+            }
+        }
+        self.bus.write_mem_access(addr, value, access);
+        self.check_watchpoints(addr, value, true);
+    }
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> Self {
+impl<M: Mem> CPU<M> {
+    pub fn new(bus: M) -> Self {
         Self {
             program_counter: 0,
             stack_pointer: STACK_RESET,
@@ -81,134 +493,510 @@ impl CPU {
             index_register_x: 0,
             index_register_y: 0,
             status: ProcessorStatus::new(),
-            bus: bus,
+            bus,
+            cycles: 0,
+            stall_cycles: 0,
+            jammed: false,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            trace: true,
+            halt_on_brk: true,
+            cycle_accurate: false,
+            decimal_mode: false,
+            power_on: PowerOnConfig::default(),
+            profile: None,
+            events: Scheduler::new(),
+            skip_interrupt_poll: false,
+            page_crossed: false,
+            branch_cycles: 0,
+            halted_on_brk: false,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            watch_hit: None,
+            call_stack: Vec::new(),
+            control: ExecutionControl::new(),
+            hooks: Vec::new(),
+            next_hook_id: 0,
+            undo: VecDeque::new(),
+            undo_depth: 0,
         }
     }
 
 
     pub fn load_test(&mut self, program: Vec<u8>) {
-        for i in 0..(program.len() as u16) {
-            self.write_mem(0x0600 + i, program[i as usize]);
-        }
-        self.write_mem_u16(0xFFFC, 0x0600);
-    }
-
-    pub fn disassemble(&self, program: Vec<u8>) {
-        let ref opcodes: HashMap<u8, &'static OpCode> = *opcodes::CPU_OPCODES_MAP;
-        let mut pos: usize = 0;
-        while pos < program.len() {
-            let addr = 0x600 + pos;
-            let opcode = opcodes.get(&program[pos]).expect(&format!("Unknown opcode {:x}", pos));
-            let mut args: Vec<u8> = Vec::new();
-            if opcode.bytes > 1 {
-                for i in 1..(opcode.bytes) {
-                    args.push(program[pos + i as usize]);
-                }
-            }
-            pos += opcode.bytes as usize;
-            println!(
-                "{:#04X}| {:#04X}: {:?} ({:02X?}) - {:?}",
-                addr,
-                opcode.opcode,
-                opcode.label,
-                args,
-                opcode.addressing_mode
-            );
+        self.load_at(0x0600, &program, true);
+    }
+
+    // Copies `program` to `base`, wrapping past $FFFF. With `set_reset_vector`
+    // the reset vector then points to `base`, even if the program covered it
+    pub fn load_at(&mut self, base: u16, program: &[u8], set_reset_vector: bool) {
+        for (i, byte) in program.iter().enumerate() {
+            self.write_mem(base.wrapping_add(i as u16), *byte);
+        }
+        if set_reset_vector {
+            self.write_mem_u16(0xFFFC, base);
         }
     }
 
+    // Loads a raw 6502 binary (no iNES header) at `base` and points the reset vector to `entry`
+    pub fn load_raw(&mut self, program: &[u8], base: u16, entry: u16) {
+        self.load_at(base, program, false);
+        self.write_mem_u16(0xFFFC, entry);
+    }
+
     pub fn load_program(&mut self, program: Vec<u8>) {
         // TODO check the length of the program
-        for i in 0..(program.len() as u16) {
-            self.write_mem(0x8000 + i, program[i as usize]);
+        self.load_at(0x8000, &program, true);
+    }
+
+    // Cold boot with `config`, which reset() then follows too. RAM is filled
+    // here, so load programs afterwards. Call reset() to start running
+    pub fn power_on(&mut self, config: PowerOnConfig) {
+        self.power_on = config;
+        if let Some(mut seed) = config.random_ram {
+            for addr in 0..0x0800u16 {
+                self.write_mem(addr, next_random(&mut seed) as u8);
+            }
+        }
+        if config.hardware_stack_pointer {
+            self.stack_pointer = 0;
         }
-        self.write_mem_u16(0xFFFC, 0x8000);
     }
 
     pub fn reset(&mut self) {
         self.program_counter = self.read_mem_u16(0xFFFC); // Address at 0xFFFC 2 bytes little endian
-        self.stack_pointer = STACK_RESET;
+        self.stack_pointer = if self.power_on.hardware_stack_pointer {
+            self.stack_pointer.wrapping_sub(3)
+        } else {
+            STACK_RESET
+        };
         self.register_accumulator = 0;
         self.index_register_x = 0;
         self.index_register_y = 0;
         self.status = ProcessorStatus::new();
+        self.status.set_flag(StatusFlag::InterruptDisable, self.power_on.interrupt_disable);
+        self.jammed = false;
+        self.events.clear();
+        self.skip_interrupt_poll = false;
+        self.call_stack.clear();
+        self.undo.clear();
+    }
+
+    pub fn reset_cycles(&mut self) {
+        // Pending events keep their distance to the current cycle
+        let mut events = Scheduler::new();
+        while let Some(cycle) = self.events.next_cycle() {
+            let event = self.events.pop_due(cycle).unwrap();
+            events.schedule(cycle.saturating_sub(self.cycles), event);
+        }
+        self.events = events;
+        self.cycles = 0;
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            pc: self.program_counter,
+            sp: self.stack_pointer,
+            a: self.register_accumulator,
+            x: self.index_register_x,
+            y: self.index_register_y,
+            p: self.status.status,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            register_accumulator: self.register_accumulator,
+            index_register_x: self.index_register_x,
+            index_register_y: self.index_register_y,
+            status: self.status.status,
+            cycles: self.cycles,
+            stall_cycles: self.stall_cycles,
+            jammed: self.jammed,
+            delayed_interrupt_disable: self
+                .events
+                .iter()
+                .map(|(_, event)| match event {
+                    CpuEvent::InterruptPollFlag(flag) => *flag,
+                })
+                .next(),
+            skip_interrupt_poll: self.skip_interrupt_poll,
+        }
+    }
+
+    // Restores registers and counters. The bus, and so memory, is left as is
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.register_accumulator = state.register_accumulator;
+        self.index_register_x = state.index_register_x;
+        self.index_register_y = state.index_register_y;
+        self.status.status = state.status;
+        self.cycles = state.cycles;
+        self.stall_cycles = state.stall_cycles;
+        self.jammed = state.jammed;
+        self.events.clear();
+        if let Some(flag) = state.delayed_interrupt_disable {
+            self.events.schedule(state.cycles, CpuEvent::InterruptPollFlag(flag));
+        }
+        self.skip_interrupt_poll = state.skip_interrupt_poll;
+    }
+
+    // Suspends the CPU for `cycles` before its next instruction, for devices
+    // taking over the bus. The next step() returns them with its own
+    pub fn stall(&mut self, cycles: u16) {
+        self.stall_cycles += cycles;
+    }
+
+    // Prints every executed instruction, on by default
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = enabled;
+    }
+
+    // Makes BRK stop execute() instead of running the interrupt, the way test
+    // programs and the snake game end. On by default
+    pub fn set_halt_on_brk(&mut self, enabled: bool) {
+        self.halt_on_brk = enabled;
+    }
+
+    // Makes execute() stop before running the instruction at `addr`. Returns
+    // false if there was already one
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    // Breakpoint addresses, in ascending order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Returns false if the same watchpoint was already set
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.insert(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.remove(&watchpoint)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    fn check_watchpoints(&mut self, addr: u16, value: u8, write: bool) {
+        if self.watch_hit.is_some() || !self.watchpoints.iter().any(|w| w.matches(addr, write)) {
+            return;
+        }
+        self.watch_hit = Some(WatchHit {
+            program_counter: self.history.back().map_or(self.program_counter, |entry| entry.program_counter),
+            address: addr,
+            value,
+            write,
+        });
+    }
+
+    // Makes the CPU do the extra bus accesses real hardware does, like the
+    // dummy read of indexed addressing crossing a page. Off by default
+    pub fn set_cycle_accurate(&mut self, enabled: bool) {
+        self.cycle_accurate = enabled;
+    }
+
+    // Makes ADC and SBC honor the D flag like an NMOS 6502. The 2A03 has no
+    // decimal mode, so it's off by default
+    pub fn set_decimal_mode(&mut self, enabled: bool) {
+        self.decimal_mode = enabled;
+    }
+
+    // Counts executions and cycles per opcode, and per instruction address
+    // with `per_address`, until stop_profiling()
+    pub fn start_profiling(&mut self, per_address: bool) {
+        self.profile = Some(Profile::new(per_address));
+    }
+
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn reset_profile(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            profile.reset();
+        }
+    }
+
+    fn profile_instruction(&mut self, code: u8, cycles: u16) {
+        if let Some(profile) = &mut self.profile {
+            let address = self.history.back().map_or(self.program_counter, |entry| entry.program_counter);
+            profile.record(address, code, cycles);
+        }
+    }
+
+    // Calls `hook` before or after every instruction step() runs, in the
+    // order hooks were added. Hooks get the CPU, so they can also change
+    // registers and memory, e.g. to cheat
+    pub fn add_hook<F>(&mut self, point: HookPoint, hook: F) -> HookId
+    where
+        F: FnMut(&mut Self, &InstructionEvent) + 'static,
+    {
+        let id = HookId(self.next_hook_id);
+        self.next_hook_id += 1;
+        self.hooks.push((id, point, Box::new(hook)));
+        id
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|(hook_id, _, _)| *hook_id != id);
+        self.hooks.len() != count
+    }
+
+    // Runs the hooks registered for `point`. They are taken out while they
+    // run, hooks added meanwhile are kept after them
+    fn run_hooks(&mut self, point: HookPoint, event: &InstructionEvent) {
+        let mut hooks = core::mem::take(&mut self.hooks);
+        for (_, _, hook) in hooks.iter_mut().filter(|(_, hook_point, _)| *hook_point == point) {
+            hook(self, event);
+        }
+        hooks.append(&mut self.hooks);
+        self.hooks = hooks;
+    }
+
+    fn instruction_event(&self, opcode: &'static OpCode, address: u16, operand_address: Option<u16>) -> InstructionEvent {
+        InstructionEvent { opcode, address, operand_address, state: self.state() }
+    }
+
+    // Handle to stop or pause execute() from another thread
+    pub fn control(&self) -> ExecutionControl {
+        self.control.clone()
+    }
+
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    // Last executed instructions, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.history.iter()
+    }
+
+    // Keeps what's needed to step back over the last `depth` instructions, 0
+    // (the default) turns it off. Only the CPU and memory are rewound: PPU,
+    // APU and I/O register writes, DMA and interrupt lines stay as they are
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
     }
 
+    pub fn undo_depth(&self) -> usize {
+        self.undo_depth
+    }
+
+    // How many steps step_back() can currently undo
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    // Undoes the last step: memory writes, registers, cycles and the call
+    // stack. Returns false when there's nothing left to undo
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.undo.pop_back() else {
+            return false;
+        };
+        for &(addr, value) in entry.writes.iter().rev() {
+            self.bus.write_mem(addr, value);
+        }
+        self.set_state(&entry.state);
+        self.call_stack = entry.call_stack;
+        self.history.pop_back();
+        self.halted_on_brk = false;
+        self.watch_hit = None;
+        true
+    }
+
+    fn record_undo(&mut self) {
+        if self.undo_depth == 0 {
+            return;
+        }
+        if self.undo.len() == self.undo_depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(UndoEntry {
+            state: self.state(),
+            call_stack: self.call_stack.clone(),
+            writes: Vec::new(),
+        });
+    }
+
+    fn record_history(&mut self, opcode: u8) {
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(TraceEntry {
+            program_counter: self.program_counter,
+            opcode,
+            operands: [
+                self.peek_mem(self.program_counter.wrapping_add(1)),
+                self.peek_mem(self.program_counter.wrapping_add(2)),
+            ],
+            register_accumulator: self.register_accumulator,
+            index_register_x: self.index_register_x,
+            index_register_y: self.index_register_y,
+            status: self.status.status,
+            stack_pointer: self.stack_pointer,
+        });
+    }
+
+    // Hardware interrupt sequence, 7 cycles: two dummy reads of the next
+    // opcode, PC and status pushed (B clear), then the vector fetched
+    fn interrupt(&mut self, vector: u16) {
+        self.read_mem_access(self.program_counter, MemAccess::DummyRead);
+        self.read_mem_access(self.program_counter, MemAccess::DummyRead);
+        self.enter_interrupt(vector, false)
+    }
+
+    fn enter_interrupt(&mut self, vector: u16, break_flag: bool) {
+        let (stack_pointer, return_address) = (self.stack_pointer, self.program_counter);
+        self.stack_push_u16(self.program_counter);
+        let b = if break_flag { 0b0001_0000 } else { 0 };
+        self.stack_push(self.status.status & 0b1110_1111 | b | 0b0010_0000);
+        self.status.set_flag(StatusFlag::InterruptDisable, true);
+        self.program_counter = self.read_mem_access_u16(vector, MemAccess::Read);
+        let kind = match (vector, break_flag) {
+            (NMI_VECTOR, _) => CallKind::Nmi,
+            (_, true) => CallKind::Brk,
+            _ => CallKind::Irq,
+        };
+        self.call_stack.push(CallFrame { kind, entry: self.program_counter, return_address, stack_pointer });
+    }
+
+    // Subroutines and interrupt handlers being run, innermost last. Frames
+    // are dropped once the stack pointer is back above them, so returns
+    // through RTS/RTI, discarded return addresses and stack resets are all
+    // followed
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
+    fn unwind_call_stack(&mut self) {
+        while self.call_stack.last().is_some_and(|frame| frame.stack_pointer <= self.stack_pointer) {
+            self.call_stack.pop();
+        }
+    }
+
+    // Meant for tests: panics if the program hits a CPU error
     pub fn load_and_execute(&mut self, program: Vec<u8>) {
         self.load_program(program);
         self.reset();
-        self.execute();
+        // Test programs can end with BRK or KIL
+        match self.execute() {
+            Ok(_) | Err(CpuError::Jammed { .. }) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn fetch(&mut self) -> u8 {
+        self.read_mem_access(self.program_counter, MemAccess::Fetch)
+    }
+
+    fn read_mem_access_u16(&mut self, addr: u16, access: MemAccess) -> u16 {
+        let little = self.read_mem_access(addr, access);
+        let big = self.read_mem_access(addr.wrapping_add(1), access);
+        u16::from_le_bytes([little, big])
+    }
+
+    // Reads a pointer without carrying into the high byte, like the 6502 does
+    // when a pointer straddles a page boundary ($10FF reads $10FF and $1000)
+    pub fn read_u16_pagewrap(&mut self, addr: u16, access: MemAccess) -> u16 {
+        let little = self.read_mem_access(addr, access);
+        let big = self.read_mem_access(addr & 0xFF00 | addr.wrapping_add(1) & 0x00FF, access);
+        u16::from_le_bytes([little, big])
     }
 
-    pub fn fetch(&self) -> u8 {
-        self.read_mem(self.program_counter)
+    // Zero page pointers wrap from $FF to $00
+    pub fn read_zp_u16(&mut self, ptr: u8, access: MemAccess) -> u16 {
+        self.read_u16_pagewrap(ptr as u16, access)
     }
 
+    // The stack pointer wraps within page 1 both ways, like on the 6502
     pub fn stack_push(&mut self, value: u8) {
-        if self.stack_pointer > 0 {
-            let pointer: u16 = STACK + self.stack_pointer as u16;
-            self.write_mem(pointer, value);
-            self.stack_pointer -= 1;
-        } else {
-            panic!("Stack Overflow!")
-        }
+        let pointer: u16 = STACK + self.stack_pointer as u16;
+        self.write_mem_access(pointer, value, MemAccess::Write);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
+    // High byte first, so the low byte ends up on top like on the 6502
     pub fn stack_push_u16(&mut self, value: u16) {
         let bytes = u16::to_le_bytes(value);
-        self.stack_push(bytes[0]);
         self.stack_push(bytes[1]);
+        self.stack_push(bytes[0]);
     }
 
     pub fn stack_pull(&mut self) -> u8 {
-        let pointer: u16 = STACK + self.stack_pointer as u16 + 1;
-        let value: u8 = self.read_mem(pointer);
-        if self.stack_pointer < STACK_RESET {
-            self.stack_pointer += 1;
-        }
-        value
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        let pointer: u16 = STACK + self.stack_pointer as u16;
+        self.read_mem_access(pointer, MemAccess::Read)
     }
 
     pub fn stack_pull_u16(&mut self) -> u16 {
         let little: u8 = self.stack_pull();
         let big: u8 = self.stack_pull();
-        u16::from_le_bytes([big, little])
+        u16::from_le_bytes([little, big])
+    }
+
+    // Adds an index register to a base address. Crossing a page, the CPU
+    // first reads from the address before the high byte is fixed up
+    fn index(&mut self, base: u16, index: u8) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        if addr & 0xFF00 != base & 0xFF00 {
+            self.page_crossed = true;
+            if self.cycle_accurate {
+                self.read_mem_access(base & 0xFF00 | addr & 0x00FF, MemAccess::DummyRead);
+            }
+        }
+        addr
     }
 
-    pub fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    pub fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
-            AddressingMode::ZeroPage => self.read_mem(self.program_counter) as u16,
+            AddressingMode::ZeroPage => self.read_mem_access(self.program_counter, MemAccess::Fetch) as u16,
             AddressingMode::ZeroPage_X => {
-                let param = self.read_mem(self.program_counter);
+                let param = self.read_mem_access(self.program_counter, MemAccess::Fetch);
                 self.index_register_x.wrapping_add(param) as u16
             }
             AddressingMode::ZeroPage_Y => {
-                let param = self.read_mem(self.program_counter);
+                let param = self.read_mem_access(self.program_counter, MemAccess::Fetch);
                 self.index_register_y.wrapping_add(param) as u16
             }
-            AddressingMode::Absolute => self.read_mem_u16(self.program_counter),
+            AddressingMode::Absolute => self.read_mem_access_u16(self.program_counter, MemAccess::Fetch),
             AddressingMode::Absolute_X => {
-                let param = self.read_mem_u16(self.program_counter);
-                param.wrapping_add(self.index_register_x as u16)
+                let param = self.read_mem_access_u16(self.program_counter, MemAccess::Fetch);
+                self.index(param, self.index_register_x)
             }
             AddressingMode::Absolute_Y => {
-                let param = self.read_mem_u16(self.program_counter);
-                param.wrapping_add(self.index_register_y as u16)
+                let param = self.read_mem_access_u16(self.program_counter, MemAccess::Fetch);
+                self.index(param, self.index_register_y)
             }
             AddressingMode::Indirect_X => {
-                let param = self.read_mem(self.program_counter);
+                let param = self.read_mem_access(self.program_counter, MemAccess::Fetch);
                 let ptr: u8 = param.wrapping_add(self.index_register_x);
-                let little: u8 = self.read_mem(ptr as u16);
-                let big: u8 = self.read_mem(ptr.wrapping_add(1) as u16);
-                u16::from_le_bytes([little, big])
+                self.read_zp_u16(ptr, MemAccess::Read)
             }
             AddressingMode::Indirect_Y => {
-                let param = self.read_mem(self.program_counter);
-                let little: u8 = self.read_mem(param as u16);
-                let big: u8 = self.read_mem(param.wrapping_add(1) as u16);
-                let deref_base: u16 = u16::from_le_bytes([little, big]);
-                deref_base.wrapping_add(self.index_register_y as u16)
+                let param = self.read_mem_access(self.program_counter, MemAccess::Fetch);
+                let deref_base: u16 = self.read_zp_u16(param, MemAccess::Read);
+                self.index(deref_base, self.index_register_y)
             }
             _ => {
                 panic!("mode {:?} is not supported", mode);
@@ -216,6 +1004,31 @@ impl CPU {
         }
     }
 
+    // Same as get_operand_address, without side effects on the bus. Returns None
+    // for modes that don't address memory
+    pub fn peek_operand_address(&self, mode: &AddressingMode) -> Option<u16> {
+        self.peek_operand_address_at(self.program_counter.wrapping_sub(1), mode)
+    }
+
+    // Same, for the instruction whose opcode is at `address`, e.g. the one
+    // about to run at PC
+    pub fn peek_operand_address_at(&self, address: u16, mode: &AddressingMode) -> Option<u16> {
+        let param = self.peek_mem(address.wrapping_add(1));
+        let param_u16 = u16::from_le_bytes([param, self.peek_mem(address.wrapping_add(2))]);
+        let peek_pointer = |ptr: u8| u16::from_le_bytes([self.peek_mem(ptr as u16), self.peek_mem(ptr.wrapping_add(1) as u16)]);
+        match mode {
+            AddressingMode::ZeroPage => Some(param as u16),
+            AddressingMode::ZeroPage_X => Some(param.wrapping_add(self.index_register_x) as u16),
+            AddressingMode::ZeroPage_Y => Some(param.wrapping_add(self.index_register_y) as u16),
+            AddressingMode::Absolute => Some(param_u16),
+            AddressingMode::Absolute_X => Some(param_u16.wrapping_add(self.index_register_x as u16)),
+            AddressingMode::Absolute_Y => Some(param_u16.wrapping_add(self.index_register_y as u16)),
+            AddressingMode::Indirect_X => Some(peek_pointer(param.wrapping_add(self.index_register_x))),
+            AddressingMode::Indirect_Y => Some(peek_pointer(param).wrapping_add(self.index_register_y as u16)),
+            _ => None,
+        }
+    }
+
     pub fn load_accumulator(&mut self, value: u8) {
         self.register_accumulator = value;
         self.status
@@ -224,21 +1037,33 @@ impl CPU {
 
     pub fn lda(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
-        let value = self.read_mem(addr);
+        let value = self.read_mem_access(addr, MemAccess::Read);
 
         self.load_accumulator(value);
     }
 
     pub fn sta(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
-        self.write_mem(addr, self.register_accumulator);
+        self.write_mem_access(addr, self.register_accumulator, MemAccess::Write);
+    }
+
+    fn in_decimal_mode(&self) -> bool {
+        self.decimal_mode && self.status.get_flag(StatusFlag::Decimal)
     }
 
     pub fn add_width_carry(&mut self, value: u8) {
+        if self.in_decimal_mode() {
+            self.add_decimal(value);
+        } else {
+            self.add_binary(value);
+        }
+    }
+
+    fn add_binary(&mut self, value: u8) {
         let carry: u8 = self.status.get_flag(StatusFlag::Carry) as u8;
         let result: u16 = self.register_accumulator as u16 + value as u16 + carry as u16;
 
-        let carry: bool = if result > 0xFF { true } else { false };
+        let carry: bool = result > 0xFF;
         let result: u8 = result as u8;
 
         self.status.set_flag(StatusFlag::Carry, carry);
@@ -248,66 +1073,145 @@ impl CPU {
         self.load_accumulator(result);
     }
 
+    // NMOS decimal ADC: N and V come from the sum before the high digit is
+    // adjusted, Z from the binary sum
+    fn add_decimal(&mut self, value: u8) {
+        let (a, v) = (self.register_accumulator as u16, value as u16);
+        let carry = self.status.get_flag(StatusFlag::Carry) as u16;
+        let binary = (a + v + carry) as u8;
+
+        let mut low = (a & 0x0F) + (v & 0x0F) + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) + (v & 0xF0) + low;
+        self.status.update_zero_and_negative_registers(binary);
+        self.status.set_flag(StatusFlag::Negative, sum & 0x80 != 0);
+        self.status.set_flag(StatusFlag::Overflow, (a ^ sum) & (v ^ sum) & 0x80 != 0);
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.status.set_flag(StatusFlag::Carry, sum > 0xFF);
+        self.register_accumulator = sum as u8;
+    }
+
+    pub fn subtract_with_carry(&mut self, value: u8) {
+        let a = self.register_accumulator as i16;
+        let borrow = !self.status.get_flag(StatusFlag::Carry) as i16;
+        // Flags are the binary ones even in decimal mode
+        self.add_binary(!value);
+        if !self.in_decimal_mode() {
+            return;
+        }
+        let v = value as i16;
+        let mut low = (a & 0x0F) - (v & 0x0F) - borrow;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+        let mut difference = (a & 0xF0) - (v & 0xF0) + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        self.register_accumulator = difference as u8;
+    }
+
     pub fn adc(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
-        let value = self.read_mem(addr);
+        let value = self.read_mem_access(addr, MemAccess::Read);
 
         self.add_width_carry(value);
     }
 
     pub fn sbc(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
-        let value = self.read_mem(addr);
+        let value = self.read_mem_access(addr, MemAccess::Read);
 
-        self.add_width_carry(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        self.subtract_with_carry(value);
     }
 
     pub fn asl(&mut self, value: u8) -> u8 {
         let last_bit = value & 0b1000_0000;
         let carry = last_bit.count_ones() != 0;
         self.status.set_flag(StatusFlag::Carry, carry);
-        value << 1
+        let result = value << 1;
+        self.status.update_zero_and_negative_registers(result);
+        result
     }
 
     pub fn lsr(&mut self, value: u8) -> u8 {
         let first_bit = value & 0b0000_0001;
         let carry = first_bit.count_ones() != 0;
         self.status.set_flag(StatusFlag::Carry, carry);
-        value >> 1
+        let result = value >> 1;
+        self.status.update_zero_and_negative_registers(result);
+        result
     }
 
     pub fn rol(&mut self, value: u8) -> u8 {
+        let carry_in = self.status.get_flag(StatusFlag::Carry) as u8;
         let last_bit = value & 0b1000_0000;
         let carry = last_bit.count_ones() != 0;
         self.status.set_flag(StatusFlag::Carry, carry);
-        (value << 1) | carry as u8
+        let result = (value << 1) | carry_in;
+        self.status.update_zero_and_negative_registers(result);
+        result
     }
 
     pub fn ror(&mut self, value: u8) -> u8 {
+        let carry_in = self.status.get_flag(StatusFlag::Carry) as u8;
         let first_bit = value & 0b0000_0001;
         let carry = first_bit.count_ones() != 0;
         self.status.set_flag(StatusFlag::Carry, carry);
-        (value >> 1) | (carry as u8).reverse_bits()
+        let result = (value >> 1) | (carry_in << 7);
+        self.status.update_zero_and_negative_registers(result);
+        result
     }
 
+    // A taken branch takes one more cycle, two when it lands in another page
     pub fn branch(&mut self, condition: bool) {
         if condition {
-            let relative_displacement: i8 = self.read_mem(self.program_counter) as i8;
-            self.program_counter = self.program_counter
-                .wrapping_add(1)
-                .wrapping_add(relative_displacement as u16);
+            let relative_displacement: i8 = self.read_mem_access(self.program_counter, MemAccess::Fetch) as i8;
+            let next = self.program_counter.wrapping_add(1);
+            self.program_counter = next.wrapping_add(relative_displacement as u16);
+            let same_page = self.program_counter & 0xFF00 == next & 0xFF00;
+            self.branch_cycles = if same_page { 1 } else { 2 };
+            // Without the page fix-up cycle, the CPU misses its interrupt poll
+            self.skip_interrupt_poll = same_page;
         }
     }
 
     pub fn compare(&mut self, mode: &AddressingMode, other: u8) {
         let addr = self.get_operand_address(mode);
-        let value = self.read_mem(addr);
+        let value = self.read_mem_access(addr, MemAccess::Read);
+
+        self.compare_value(other, value);
+    }
 
+    fn compare_value(&mut self, other: u8, value: u8) {
         self.status.set_flag(StatusFlag::Carry, other >= value);
         self.status
             .update_zero_and_negative_registers(other.wrapping_sub(value));
     }
 
+    // Reads the operand, writes it back unchanged (the dummy write every RMW
+    // instruction does), then writes the modified value
+    fn read_modify_write<F>(&mut self, mode: &AddressingMode, modify: F) -> u8
+    where
+        F: FnOnce(&mut Self, u8) -> u8,
+    {
+        let addr = self.get_operand_address(mode);
+        let value = self.read_mem_access(addr, MemAccess::Read);
+        self.write_mem_access(addr, value, MemAccess::DummyWrite);
+        let result = modify(self, value);
+        self.write_mem_access(addr, result, MemAccess::Write);
+        result
+    }
+
+    fn read_operand(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.get_operand_address(mode);
+        self.read_mem_access(addr, MemAccess::Read)
+    }
+
     pub fn decrement(&mut self, value: u8) -> u8 {
         let result = value.wrapping_sub(1);
         self.status.update_zero_and_negative_registers(result);
@@ -320,303 +1224,565 @@ impl CPU {
         result
     }
 
-    pub fn execute(&mut self) {
-        self.execute_with_callback(|_| {});
+    pub fn execute(&mut self) -> Result<StopReason, CpuError> {
+        self.execute_with_callback(|_| {})
+    }
+
+    // Lazily runs the program, one instruction per item. Ends after a BRK in
+    // halt mode or after yielding an error. Unlike execute(), it doesn't stop
+    // at breakpoints, watchpoints or ExecutionControl::stop(): the caller
+    // stops pulling items instead
+    pub fn instructions(&mut self) -> Instructions<'_, M> {
+        Instructions { cpu: self, done: false }
     }
 
-    pub fn execute_with_callback<F>(&mut self, mut callback: F)
+    // Runs until BRK (in halt mode), a breakpoint or a watchpoint, or until an
+    // error, KIL included. A watchpoint stops after the whole instruction ran.
+    // On an error PC points at the faulting instruction, so the caller can fix
+    // things up and call it again. The first instruction always runs, so
+    // calling it again resumes from a breakpoint
+    pub fn execute_with_callback<F>(&mut self, callback: F) -> Result<StopReason, CpuError>
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut Self),
     {
-        let ref opcodes: HashMap<u8, &'static OpCode> = *opcodes::CPU_OPCODES_MAP;
+        self.run(callback, |_| false)
+    }
+
+    // Like execute(), but also stops before the next instruction once
+    // `predicate` holds, e.g. |cpu| cpu.program_counter == 0x8010 or
+    // |cpu| cpu.cycles >= 29_781. Checked before every instruction, the first
+    // one included
+    pub fn run_until<P>(&mut self, predicate: P) -> Result<StopReason, CpuError>
+    where
+        P: FnMut(&Self) -> bool,
+    {
+        self.run(|_| {}, predicate)
+    }
+
+    fn run<F, P>(&mut self, mut callback: F, mut predicate: P) -> Result<StopReason, CpuError>
+    where
+        F: FnMut(&mut Self),
+        P: FnMut(&Self) -> bool,
+    {
+        let mut resuming = true;
         loop {
+            if self.control.should_stop() {
+                return Ok(StopReason::Stopped);
+            }
+            if predicate(self) {
+                return Ok(StopReason::Condition);
+            }
+            if !resuming && self.breakpoints.contains(&self.program_counter) {
+                return Ok(StopReason::Breakpoint(self.program_counter));
+            }
+            resuming = false;
             callback(self);
-            let code = self.fetch();
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-
-            let opcode = opcodes
-                .get(&code)
-                .expect(&format!("Unknown opcode {:x}", code));
-            println!(
-                "{:#04X}| {}",
+            self.step()?;
+            if let Some(hit) = self.watch_hit.take() {
+                return Ok(StopReason::Watchpoint(hit));
+            }
+            if self.halted_on_brk {
+                return Ok(StopReason::Brk);
+            }
+        }
+    }
+
+    // Sits out any stall, services a pending interrupt, then runs one
+    // instruction. Returns the cycles taken, page crossing penalties
+    // included. KIL and every step after it until reset do
+    // nothing but return CpuError::Jammed. Breakpoints are ignored, single
+    // stepping is how a debugger moves off one
+    pub fn step(&mut self) -> Result<u16, CpuError> {
+        self.halted_on_brk = false;
+        self.watch_hit = None;
+        if self.jammed {
+            return Err(CpuError::Jammed { address: self.program_counter });
+        }
+        self.record_undo();
+        let mut cycles = core::mem::take(&mut self.stall_cycles);
+        let mut interrupt_disable = self.status.get_flag(StatusFlag::InterruptDisable);
+        while let Some(event) = self.events.pop_due(self.cycles) {
+            match event {
+                CpuEvent::InterruptPollFlag(flag) => interrupt_disable = flag,
+            }
+        }
+        let poll = !core::mem::take(&mut self.skip_interrupt_poll);
+        if poll && self.bus.take_nmi() {
+            cycles += INTERRUPT_CYCLES;
+            self.interrupt(NMI_VECTOR);
+            if self.trace {
+                log!("NMI -> {:#06X}", self.program_counter);
+            }
+        } else if poll && self.bus.irq_pending() && !interrupt_disable {
+            // IRQ is level-triggered, the handler has to acknowledge the source
+            cycles += INTERRUPT_CYCLES;
+            self.interrupt(IRQ_VECTOR);
+            if self.trace {
+                log!("IRQ -> {:#06X}", self.program_counter);
+            }
+        }
+        let code = self.fetch();
+        self.record_history(code);
+        // PC stays on an unknown opcode, callers can skip it or stop there. The
+        // stall and any interrupt serviced before it still count
+        let Some(opcode) = opcodes::CPU_OPCODE_TABLE[code as usize] else {
+            self.cycles += cycles as u64;
+            return Err(CpuError::UnknownOpcode { opcode: code, address: self.program_counter });
+        };
+        self.program_counter += 1;
+        let program_counter_state = self.program_counter;
+        let event = (!self.hooks.is_empty()).then(|| {
+            let operand_address = self.peek_operand_address(&opcode.addressing_mode);
+            let mut event = self.instruction_event(opcode, program_counter_state - 1, operand_address);
+            event.state.program_counter = event.address;
+            event
+        });
+        if let Some(event) = &event {
+            self.run_hooks(HookPoint::Before, event);
+        }
+        let interrupt_disable = self.status.get_flag(StatusFlag::InterruptDisable);
+        let cycles_before_instruction = cycles;
+        cycles += opcode.cycles;
+        self.page_crossed = false;
+        self.branch_cycles = 0;
+        self.cycles += cycles as u64;
+        if self.trace {
+            let operands: Vec<u8> = (0..opcode.bytes as u16 - 1)
+                .map(|i| self.peek_mem(self.program_counter.wrapping_add(i)))
+                .collect();
+            let target = match self.peek_operand_address(&opcode.addressing_mode) {
+                Some(addr) => format!(" -> {}", self.bus.describe_address(addr)),
+                None => String::new(),
+            };
+            log!(
+                "{:#04X}| {} {:02X?}{}",
                 self.program_counter - 1,
-                opcode.label
+                opcode.label,
+                operands,
+                target
             );
-            match opcode.label {
-                "ADC" => {
-                    // Add with carry
-                    self.adc(&opcode.addressing_mode);
-                }
-                "AND" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value: u8 = self.read_mem(addr);
-                    self.register_accumulator = self.register_accumulator.bitand(value);
-                    self.status
-                        .update_zero_and_negative_registers(self.register_accumulator);
-                }
-                "ASL" => {
-                    // Arithmetic Shift Left
-                    match opcode.addressing_mode {
-                        AddressingMode::NoneAddressing => {
-                            self.register_accumulator = self.asl(self.register_accumulator);
-                        }
-                        _ => {
-                            let addr = self.get_operand_address(&opcode.addressing_mode);
-                            let value = self.read_mem(addr);
-                            let result = self.asl(value);
-                            self.write_mem(addr, result);
-                        }
+        }
+        match opcode.instruction {
+            Instruction::ADC => {
+                // Add with carry
+                self.adc(&opcode.addressing_mode);
+            }
+            Instruction::AND => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value: u8 = self.read_mem_access(addr, MemAccess::Read);
+                self.register_accumulator = self.register_accumulator.bitand(value);
+                self.status
+                    .update_zero_and_negative_registers(self.register_accumulator);
+            }
+            Instruction::ASL => {
+                // Arithmetic Shift Left
+                match opcode.addressing_mode {
+                    AddressingMode::NoneAddressing => {
+                        self.register_accumulator = self.asl(self.register_accumulator);
                     }
-                    self.status
-                        .update_zero_and_negative_registers(self.register_accumulator);
-                }
-                "BCC" => self.branch(!self.status.get_flag(StatusFlag::Carry)),
-                "BCS" => self.branch(self.status.get_flag(StatusFlag::Carry)),
-                "BEQ" => self.branch(self.status.get_flag(StatusFlag::Zero)),
-                "BIT" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let result = self.register_accumulator.bitand(self.read_mem(addr));
-                    let overflow = result & 0x40 != 0;
-                    self.status.set_flag(StatusFlag::Overflow, overflow);
-                    self.status.update_zero_and_negative_registers(result);
-                }
-                "BMI" => self.branch(self.status.get_flag(StatusFlag::Negative)),
-                "BNE" => self.branch(!self.status.get_flag(StatusFlag::Zero)),
-                "BPL" => self.branch(!self.status.get_flag(StatusFlag::Negative)),
-                "BRK" => {
-                    // Break
-                    return;
-                }
-                "BVC" => self.branch(!self.status.get_flag(StatusFlag::Overflow)),
-                "BVS" => self.branch(self.status.get_flag(StatusFlag::Overflow)),
-                "CLC" => self.status.set_flag(StatusFlag::Carry, false),
-                "CLD" => self.status.set_flag(StatusFlag::Decimal, false),
-                "CLI" => self.status.set_flag(StatusFlag::InterruptDisable, false),
-                "CLV" => self.status.set_flag(StatusFlag::Overflow, false),
-                "CMP" => self.compare(&opcode.addressing_mode, self.register_accumulator),
-                "CPX" => self.compare(&opcode.addressing_mode, self.index_register_x),
-                "CPY" => self.compare(&opcode.addressing_mode, self.index_register_y),
-                "DEC" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    let result = self.decrement(value);
-                    self.write_mem(addr, result);
-                }
-                "DEX" => self.index_register_x = self.decrement(self.index_register_x),
-                "DEY" => self.index_register_y = self.decrement(self.index_register_y),
-                "EOR" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    let result = self.register_accumulator.bitxor(value);
-                    self.load_accumulator(result);
-                }
-                "INC" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    let result = self.increment(value);
-                    self.write_mem(addr, result);
-                }
-                "INX" => self.index_register_x = self.increment(self.index_register_x),
-                "INY" => self.index_register_y = self.increment(self.index_register_y),
-                "JMP" => {
-                    // Jump
-                    match opcode.addressing_mode {
-                        AddressingMode::Absolute => {
-                            let addr = self.get_operand_address(&opcode.addressing_mode);
-                            self.program_counter = addr;
-                        }
-                        _ => {
-                            // Indirect
-                            let addr = self.read_mem_u16(self.program_counter);
-
-                            let indirect_ref = if addr & 0x00FF == 0x00FF {
-                                // 6502 page boundary bug
-                                // https://www.nesdev.org/obelisk-6502-guide/reference.html#JMP
-                                let little = self.read_mem(addr);
-                                let big = self.read_mem(addr & 0xFF00);
-                                u16::from_le_bytes([little, big])
-                            } else {
-                                self.read_mem_u16(addr)
-                            };
-
-                            self.program_counter = indirect_ref;
-                        }
+                    _ => {
+                        let addr = self.get_operand_address(&opcode.addressing_mode);
+                        let value = self.read_mem_access(addr, MemAccess::Read);
+                        self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                        let result = self.asl(value);
+                        self.write_mem_access(addr, result, MemAccess::Write);
                     }
                 }
-                "JSR" => {
-                    // Jump To Subroutine
-                    self.stack_push_u16(self.program_counter + 1); // + 2 - 1
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    self.program_counter = addr;
-                }
-                "LDA" => {
-                    // Load Accumulator
-                    self.lda(&opcode.addressing_mode);
-                }
-                "LDX" => {
-                    // Load X Register
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    self.index_register_x = value;
-                    self.status.update_zero_and_negative_registers(value);
-                }
-                "LDY" => {
-                    // Load Y Register
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    self.index_register_y = value;
-                    self.status.update_zero_and_negative_registers(value);
-                }
-                "LSR" => {
-                    // Logical Shift Right
-                    match opcode.addressing_mode {
-                        AddressingMode::NoneAddressing => {
-                            self.register_accumulator = self.lsr(self.register_accumulator);
-                        }
-                        _ => {
-                            let addr = self.get_operand_address(&opcode.addressing_mode);
-                            let value = self.read_mem(addr);
-                            let result = self.lsr(value);
-                            self.write_mem(addr, result);
-                        }
+            }
+            Instruction::BCC => self.branch(!self.status.get_flag(StatusFlag::Carry)),
+            Instruction::BCS => self.branch(self.status.get_flag(StatusFlag::Carry)),
+            Instruction::BEQ => self.branch(self.status.get_flag(StatusFlag::Zero)),
+            Instruction::BIT => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                // N and V come straight from the operand, Z from the AND
+                self.status.set_flag(StatusFlag::Negative, value & 0x80 != 0);
+                self.status.set_flag(StatusFlag::Overflow, value & 0x40 != 0);
+                self.status
+                    .set_flag(StatusFlag::Zero, self.register_accumulator.bitand(value) == 0);
+            }
+            Instruction::BMI => self.branch(self.status.get_flag(StatusFlag::Negative)),
+            Instruction::BNE => self.branch(!self.status.get_flag(StatusFlag::Zero)),
+            Instruction::BPL => self.branch(!self.status.get_flag(StatusFlag::Negative)),
+            Instruction::BRK => {
+                // Break
+                if self.halt_on_brk {
+                    self.halted_on_brk = true;
+                    self.profile_instruction(code, cycles - cycles_before_instruction);
+                    if let Some(event) = event {
+                        self.run_hooks(HookPoint::After, &InstructionEvent { state: self.state(), ..event });
                     }
-                    self.status
-                        .update_zero_and_negative_registers(self.register_accumulator);
-                }
-                "NOP" => {}
-                "ORA" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    let value = self.read_mem(addr);
-                    let result = self.register_accumulator.bitor(value);
-                    self.load_accumulator(result);
-                }
-                "PHA" => {
-                    // Push Accumulator
-                    self.stack_push(self.register_accumulator);
+                    return Ok(cycles);
                 }
-                "PHP" => {
-                    // Push Processor Status
-                    self.status.set_flag(StatusFlag::B, true);
-                    self.stack_push(self.status.status);
-                }
-                "PLA" => {
-                    // Pull Accumulator
-                    let value = self.stack_pull();
-                    self.load_accumulator(value);
-                }
-                "PLP" => {
-                    // Pull Processor Status
-                    let status: u8 = self.stack_pull();
-                    self.status.set_from_byte(status);
-                }
-                "ROL" => {
-                    // Rotate Left
-                    match opcode.addressing_mode {
-                        AddressingMode::NoneAddressing => {
-                            self.register_accumulator = self.rol(self.register_accumulator);
-                        }
-                        _ => {
-                            let addr = self.get_operand_address(&opcode.addressing_mode);
-                            let value = self.read_mem(addr);
-                            let result = self.rol(value);
-                            self.write_mem(addr, result);
-                        }
+                // The byte after BRK is padding, skipped by the return address
+                self.read_mem_access(self.program_counter, MemAccess::DummyRead);
+                self.program_counter = self.program_counter.wrapping_add(1);
+                self.enter_interrupt(IRQ_VECTOR, true);
+            }
+            Instruction::BVC => self.branch(!self.status.get_flag(StatusFlag::Overflow)),
+            Instruction::BVS => self.branch(self.status.get_flag(StatusFlag::Overflow)),
+            Instruction::CLC => self.status.set_flag(StatusFlag::Carry, false),
+            Instruction::CLD => self.status.set_flag(StatusFlag::Decimal, false),
+            Instruction::CLI => self.status.set_flag(StatusFlag::InterruptDisable, false),
+            Instruction::CLV => self.status.set_flag(StatusFlag::Overflow, false),
+            Instruction::CMP => self.compare(&opcode.addressing_mode, self.register_accumulator),
+            Instruction::CPX => self.compare(&opcode.addressing_mode, self.index_register_x),
+            Instruction::CPY => self.compare(&opcode.addressing_mode, self.index_register_y),
+            Instruction::DEC => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                let result = self.decrement(value);
+                self.write_mem_access(addr, result, MemAccess::Write);
+            }
+            Instruction::DEX => self.index_register_x = self.decrement(self.index_register_x),
+            Instruction::DEY => self.index_register_y = self.decrement(self.index_register_y),
+            Instruction::EOR => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                let result = self.register_accumulator.bitxor(value);
+                self.load_accumulator(result);
+            }
+            Instruction::INC => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                let result = self.increment(value);
+                self.write_mem_access(addr, result, MemAccess::Write);
+            }
+            Instruction::INX => self.index_register_x = self.increment(self.index_register_x),
+            Instruction::INY => self.index_register_y = self.increment(self.index_register_y),
+            Instruction::JMP => {
+                // Jump
+                match opcode.addressing_mode {
+                    AddressingMode::Absolute => {
+                        let addr = self.get_operand_address(&opcode.addressing_mode);
+                        self.program_counter = addr;
                     }
-                    self.status
-                        .update_zero_and_negative_registers(self.register_accumulator);
-                }
-                "ROR" => {
-                    // Rotate Right
-                    match opcode.addressing_mode {
-                        AddressingMode::NoneAddressing => {
-                            self.register_accumulator = self.ror(self.register_accumulator);
-                        }
-                        _ => {
-                            let addr = self.get_operand_address(&opcode.addressing_mode);
-                            let value = self.read_mem(addr);
-                            let result = self.ror(value);
-                            self.write_mem(addr, result);
-                        }
+                    _ => {
+                        // Indirect
+                        let addr = self.read_mem_access_u16(self.program_counter, MemAccess::Fetch);
+
+                        // 6502 page boundary bug
+                        // https://www.nesdev.org/obelisk-6502-guide/reference.html#JMP
+                        let indirect_ref = self.read_u16_pagewrap(addr, MemAccess::Read);
+
+                        self.program_counter = indirect_ref;
                     }
-                    self.status
-                        .update_zero_and_negative_registers(self.register_accumulator);
-                }
-                "RTI" => {
-                    // Return From Interrupt
-                    let status: u8 = self.stack_pull();
-                    self.status.set_from_byte(status);
-                    let pc: u16 = self.stack_pull_u16();
-                    self.program_counter = pc;
                 }
-                "RTS" => self.program_counter = self.stack_pull_u16() + 1,
-                "SBC" => {
-                    // Subtract with carry
-                    self.sbc(&opcode.addressing_mode);
+            }
+            Instruction::JSR => {
+                // Jump To Subroutine
+                let stack_pointer = self.stack_pointer;
+                self.stack_push_u16(self.program_counter + 1); // + 2 - 1
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                self.program_counter = addr;
+                self.call_stack.push(CallFrame {
+                    kind: CallKind::Subroutine,
+                    entry: addr,
+                    return_address: program_counter_state + 2,
+                    stack_pointer,
+                });
+            }
+            Instruction::KIL => {
+                // Jam: the CPU stops fetching until the next reset
+                self.program_counter -= 1;
+                self.jammed = true;
+                return Err(CpuError::Jammed { address: self.program_counter });
+            }
+            Instruction::LDA => {
+                // Load Accumulator
+                self.lda(&opcode.addressing_mode);
+            }
+            Instruction::LDX => {
+                // Load X Register
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                self.index_register_x = value;
+                self.status.update_zero_and_negative_registers(value);
+            }
+            Instruction::LDY => {
+                // Load Y Register
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                self.index_register_y = value;
+                self.status.update_zero_and_negative_registers(value);
+            }
+            Instruction::LSR => {
+                // Logical Shift Right
+                match opcode.addressing_mode {
+                    AddressingMode::NoneAddressing => {
+                        self.register_accumulator = self.lsr(self.register_accumulator);
+                    }
+                    _ => {
+                        let addr = self.get_operand_address(&opcode.addressing_mode);
+                        let value = self.read_mem_access(addr, MemAccess::Read);
+                        self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                        let result = self.lsr(value);
+                        self.write_mem_access(addr, result, MemAccess::Write);
+                    }
                 }
-                "SEC" => self.status.set_flag(StatusFlag::Carry, true),
-                "SED" => self.status.set_flag(StatusFlag::Decimal, true),
-                "SEI" => self.status.set_flag(StatusFlag::InterruptDisable, true),
-                "STA" => {
-                    // Store Accumulator
-                    self.sta(&opcode.addressing_mode);
+            }
+            Instruction::NOP => {
+                // The unofficial ones read their operand
+                if !matches!(opcode.addressing_mode, AddressingMode::NoneAddressing) {
+                    self.read_operand(&opcode.addressing_mode);
                 }
-                "STX" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    self.write_mem(addr, self.index_register_x);
+            }
+            Instruction::ORA => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.read_mem_access(addr, MemAccess::Read);
+                let result = self.register_accumulator.bitor(value);
+                self.load_accumulator(result);
+            }
+            Instruction::PHA => {
+                // Push Accumulator
+                self.stack_push(self.register_accumulator);
+            }
+            Instruction::PHP => {
+                // Push Processor Status
+                // B only exists in the pushed copy
+                self.stack_push(self.status.status | 0b0011_0000);
+            }
+            Instruction::PLA => {
+                // Pull Accumulator
+                let value = self.stack_pull();
+                self.load_accumulator(value);
+            }
+            Instruction::PLP => {
+                // Pull Processor Status
+                let status: u8 = self.stack_pull();
+                self.status.set_from_byte(status & 0b1110_1111 | 0b0010_0000);
+            }
+            Instruction::ROL => {
+                // Rotate Left
+                match opcode.addressing_mode {
+                    AddressingMode::NoneAddressing => {
+                        self.register_accumulator = self.rol(self.register_accumulator);
+                    }
+                    _ => {
+                        let addr = self.get_operand_address(&opcode.addressing_mode);
+                        let value = self.read_mem_access(addr, MemAccess::Read);
+                        self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                        let result = self.rol(value);
+                        self.write_mem_access(addr, result, MemAccess::Write);
+                    }
                 }
-                "STY" => {
-                    let addr = self.get_operand_address(&opcode.addressing_mode);
-                    self.write_mem(addr, self.index_register_y);
+            }
+            Instruction::ROR => {
+                // Rotate Right
+                match opcode.addressing_mode {
+                    AddressingMode::NoneAddressing => {
+                        self.register_accumulator = self.ror(self.register_accumulator);
+                    }
+                    _ => {
+                        let addr = self.get_operand_address(&opcode.addressing_mode);
+                        let value = self.read_mem_access(addr, MemAccess::Read);
+                        self.write_mem_access(addr, value, MemAccess::DummyWrite);
+                        let result = self.ror(value);
+                        self.write_mem_access(addr, result, MemAccess::Write);
+                    }
                 }
-                "TAX" => {
-                    // Transfer Accumulator to register X
-                    self.index_register_x = self.register_accumulator;
+            }
+            Instruction::RTI => {
+                // Return From Interrupt
+                let status: u8 = self.stack_pull();
+                self.status.set_from_byte(status & 0b1110_1111 | 0b0010_0000);
+                let pc: u16 = self.stack_pull_u16();
+                self.program_counter = pc;
+            }
+            Instruction::RTS => self.program_counter = self.stack_pull_u16() + 1,
+            Instruction::SBC => {
+                // Subtract with carry
+                self.sbc(&opcode.addressing_mode);
+            }
+            Instruction::SEC => self.status.set_flag(StatusFlag::Carry, true),
+            Instruction::SED => self.status.set_flag(StatusFlag::Decimal, true),
+            Instruction::SEI => self.status.set_flag(StatusFlag::InterruptDisable, true),
+            Instruction::STA => {
+                // Store Accumulator
+                self.sta(&opcode.addressing_mode);
+            }
+            Instruction::STX => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                self.write_mem_access(addr, self.index_register_x, MemAccess::Write);
+            }
+            Instruction::STY => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                self.write_mem_access(addr, self.index_register_y, MemAccess::Write);
+            }
+            Instruction::TAX => {
+                // Transfer Accumulator to register X
+                self.index_register_x = self.register_accumulator;
+
+                self.status
+                    .update_zero_and_negative_registers(self.index_register_x);
+            }
+            Instruction::TAY => {
+                // Transfer Accumulator to register Y
+                self.index_register_y = self.register_accumulator;
+
+                self.status
+                    .update_zero_and_negative_registers(self.index_register_y);
+            }
+            Instruction::TSX => {
+                // Transfer Stack Pointer to X
+                self.index_register_x = self.stack_pointer;
+                self.status.update_zero_and_negative_registers(self.stack_pointer);
+            },
+            Instruction::TXA => self.load_accumulator(self.index_register_x),
+            Instruction::TXS => {
+                // Transfer X to Stack Pointer
+                self.stack_pointer = self.index_register_x;
+            },
+            Instruction::TYA => self.load_accumulator(self.index_register_y),
+
+            // Unofficial opcodes
+            Instruction::ALR => {
+                let value = self.read_operand(&opcode.addressing_mode);
+                self.register_accumulator = self.lsr(self.register_accumulator & value);
+            }
+            Instruction::ANC => {
+                let value = self.read_operand(&opcode.addressing_mode);
+                self.load_accumulator(self.register_accumulator & value);
+                let negative = self.status.get_flag(StatusFlag::Negative);
+                self.status.set_flag(StatusFlag::Carry, negative);
+            }
+            Instruction::ARR => {
+                let value = self.read_operand(&opcode.addressing_mode);
+                let result = self.ror(self.register_accumulator & value);
+                self.register_accumulator = result;
+                // C and V come from bits 6 and 5 of the result, not from the rotate
+                self.status.set_flag(StatusFlag::Carry, result & 0x40 != 0);
+                self.status
+                    .set_flag(StatusFlag::Overflow, (result >> 6 ^ result >> 5) & 1 != 0);
+            }
+            Instruction::AXS => {
+                let value = self.read_operand(&opcode.addressing_mode);
+                let masked = self.register_accumulator & self.index_register_x;
+                self.compare_value(masked, value);
+                self.index_register_x = masked.wrapping_sub(value);
+            }
+            Instruction::DCP => {
+                let result = self.read_modify_write(&opcode.addressing_mode, |_, value| value.wrapping_sub(1));
+                self.compare_value(self.register_accumulator, result);
+            }
+            Instruction::ISB => {
+                let result = self.read_modify_write(&opcode.addressing_mode, |_, value| value.wrapping_add(1));
+                self.subtract_with_carry(result);
+            }
+            Instruction::LAX => {
+                let value = self.read_operand(&opcode.addressing_mode);
+                self.load_accumulator(value);
+                self.index_register_x = value;
+            }
+            Instruction::RLA => {
+                let result = self.read_modify_write(&opcode.addressing_mode, CPU::rol);
+                self.load_accumulator(self.register_accumulator & result);
+            }
+            Instruction::RRA => {
+                let result = self.read_modify_write(&opcode.addressing_mode, CPU::ror);
+                self.add_width_carry(result);
+            }
+            Instruction::SAX => {
+                let addr = self.get_operand_address(&opcode.addressing_mode);
+                let value = self.register_accumulator & self.index_register_x;
+                self.write_mem_access(addr, value, MemAccess::Write);
+            }
+            Instruction::SLO => {
+                let result = self.read_modify_write(&opcode.addressing_mode, CPU::asl);
+                self.load_accumulator(self.register_accumulator | result);
+            }
+            Instruction::SRE => {
+                let result = self.read_modify_write(&opcode.addressing_mode, CPU::lsr);
+                self.load_accumulator(self.register_accumulator ^ result);
+            }
+
+        }
+
+        if program_counter_state == self.program_counter {
+            self.program_counter += (opcode.bytes - 1) as u16;
+        }
+        let mut extra_cycles = core::mem::take(&mut self.branch_cycles);
+        if self.page_crossed && opcode.page_cross_penalty() {
+            extra_cycles += 1;
+        }
+        cycles += extra_cycles;
+        self.cycles += extra_cycles as u64;
+        if matches!(opcode.instruction, Instruction::CLI | Instruction::SEI | Instruction::PLP)
+            && interrupt_disable != self.status.get_flag(StatusFlag::InterruptDisable)
+        {
+            // Due at the next instruction's poll
+            self.events.schedule(self.cycles, CpuEvent::InterruptPollFlag(interrupt_disable));
+        }
+        self.profile_instruction(code, cycles - cycles_before_instruction);
+        self.unwind_call_stack();
+        if let Some(event) = event {
+            self.run_hooks(HookPoint::After, &InstructionEvent { state: self.state(), ..event });
+        }
+        self.bus.tick(cycles);
+        if self.bus.take_oam_dma().is_some() {
+            self.stall(OAM_DMA_CYCLES + (self.cycles % 2) as u16);
+        }
+        if let Some(addr) = self.bus.take_dmc_dma() {
+            // Serviced between instructions: the CPU doesn't run cycle by
+            // cycle, so the shorter stall after a write cycle isn't modelled
+            let value = self.read_mem_access(addr, MemAccess::Dma);
+            self.bus.complete_dmc_dma(value);
+            self.stall(if self.stall_cycles > 0 { DMC_DMA_CYCLES_DURING_OAM_DMA } else { DMC_DMA_CYCLES });
+        }
+        Ok(cycles)
+    }
+}
 
-                    self.status
-                        .update_zero_and_negative_registers(self.index_register_x);
-                }
-                "TAY" => {
-                    // Transfer Accumulator to register Y
-                    self.index_register_y = self.register_accumulator;
+// An executed instruction: the state it started from and the cycles it took
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionRecord {
+    pub opcode: &'static OpCode,
+    pub before: TraceEntry,
+    pub cycles: u16,
+}
 
-                    self.status
-                        .update_zero_and_negative_registers(self.index_register_y);
-                }
-                "TSX" => {
-                    // Transfer Stack Pointer to X
-                    self.index_register_x = self.stack_pointer;
-                    self.status.update_zero_and_negative_registers(self.stack_pointer);
-                },
-                "TXA" => self.load_accumulator(self.index_register_x),
-                "TXS" => {
-                    // Transfer X to Stack Pointer
-                    self.stack_pointer = self.index_register_x;
-                    self.status.update_zero_and_negative_registers(self.index_register_x);
-                },
-                "TYA" => self.load_accumulator(self.index_register_y),
+pub struct Instructions<'a, M: Mem = Bus> {
+    cpu: &'a mut CPU<M>,
+    done: bool,
+}
 
-                _ => todo!(),
-            }
+impl<M: Mem> Iterator for Instructions<'_, M> {
+    type Item = Result<InstructionRecord, CpuError>;
 
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.bytes - 1) as u16;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.cpu.jammed {
+            return None;
         }
+        let cycles = match self.cpu.step() {
+            Ok(cycles) => cycles,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        self.done = self.cpu.halted_on_brk;
+        let before = *self.cpu.history.back()?;
+        Some(Ok(InstructionRecord {
+            opcode: opcodes::CPU_OPCODE_TABLE[before.opcode as usize]?,
+            before,
+            cycles,
+        }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::irq::IrqSource;
     use crate::rom::ROM;
     use rstest::*;
     use super::*;
 
     #[fixture]
-    pub fn cpu() -> CPU { 
+    pub fn cpu() -> CPU {
         let bus = Bus::new(ROM::empty());
-        let mut cpu = CPU::new(bus);
-        cpu
+        CPU::new(bus)
     }
 
 
@@ -667,6 +1833,23 @@ mod tests {
         assert_eq!(cpu.read_mem(0x10), 0x42);
     }
 
+    #[rstest]
+    fn test_sta_absolute(mut cpu: CPU) {
+        // LDA #$42, STA $0210, LDX #$01, STA $0210,X
+        cpu.load_and_execute(vec![0xA9, 0x42, 0x8D, 0x10, 0x02, 0xA2, 0x01, 0x9D, 0x10, 0x02]);
+        assert_eq!(cpu.read_mem(0x0210), 0x42);
+        assert_eq!(cpu.read_mem(0x0211), 0x42);
+    }
+
+    #[rstest]
+    fn test_ldy_sty_zero_page_x(mut cpu: CPU) {
+        cpu.write_mem(0x11, 0x42);
+        // LDX #$01, LDY $10,X, STY $20,X
+        cpu.load_and_execute(vec![0xA2, 0x01, 0xB4, 0x10, 0x94, 0x20]);
+        assert_eq!(cpu.index_register_y, 0x42);
+        assert_eq!(cpu.read_mem(0x21), 0x42);
+    }
+
     #[rstest]
     fn test_adc(mut cpu: CPU) {
         cpu.write_mem(0x10, 0x55);
@@ -713,6 +1896,65 @@ mod tests {
         assert_eq!(cpu.register_accumulator, 0xFA);
     }
 
+    #[rstest]
+    fn test_decimal_mode(mut cpu: CPU) {
+        let carry = |cpu: &CPU| cpu.status.get_flag(StatusFlag::Carry);
+        // SED / CLC / LDA #$09 / ADC #$01: the D flag does nothing on a 2A03
+        cpu.load_and_execute(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x0A);
+
+        cpu.set_decimal_mode(true);
+        cpu.load_and_execute(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x10);
+        assert!(!carry(&cpu));
+        // 99 + 1 = 00, carry set; Z comes from the binary sum $9A
+        cpu.load_and_execute(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x00);
+        assert!(carry(&cpu));
+        assert!(!cpu.status.get_flag(StatusFlag::Zero));
+        // SEC / LDA #$10 / SBC #$01 = 09, no borrow
+        cpu.load_and_execute(vec![0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x09);
+        assert!(carry(&cpu));
+        // 00 - 1 = 99, borrow
+        cpu.load_and_execute(vec![0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x99);
+        assert!(!carry(&cpu));
+        // CLD goes back to binary
+        cpu.load_and_execute(vec![0xF8, 0xD8, 0x18, 0xA9, 0x09, 0x69, 0x01]);
+        assert_eq!(cpu.register_accumulator, 0x0A);
+    }
+
+    #[rstest]
+    fn test_load_raw(mut cpu: CPU) {
+        cpu.load_raw(&[0xA9, 0x42, 0x00], 0xC000, 0xC000);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0xC000);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.register_accumulator, 0x42);
+        // Programs can reach the very end of the address space
+        cpu.load_raw(&[0xCA, 0xFE], 0xFFFE, 0xFFFE);
+        assert_eq!(cpu.read_mem_u16(0xFFFE), 0xFECA);
+    }
+
+    #[rstest]
+    fn test_load_at(mut cpu: CPU) {
+        // LDA #$42 / BRK, in zero page
+        cpu.load_at(0x0010, &[0xA9, 0x42, 0x00], true);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0010);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.register_accumulator, 0x42);
+
+        // The vector is left alone without set_reset_vector
+        cpu.load_at(0xE000, &[0xE8, 0x00], false);
+        assert_eq!(cpu.read_mem_u16(0xFFFC), 0x0010);
+        // Covering the vectors, the reset vector still wins
+        cpu.load_at(0xFFF8, &[0xEA; 8], true);
+        assert_eq!(cpu.read_mem_u16(0xFFFC), 0xFFF8);
+        assert_eq!(cpu.read_mem_u16(0xFFFE), 0xEAEA);
+    }
+
     #[rstest]
     fn test_get_operand_address_zero_page(mut cpu: CPU) {
         cpu.load_program(vec![0x10]);
@@ -721,6 +1963,27 @@ mod tests {
         assert_eq!(addr, 0x10);
     }
 
+    #[rstest]
+    fn test_peek_mem(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x42);
+        assert_eq!(cpu.peek_mem(0x10), 0x42);
+        // PPU registers have read side effects, peeking them must not touch the device
+        assert_eq!(cpu.peek_mem(0x2002), 0);
+    }
+
+    #[rstest]
+    fn test_peek_operand_address(mut cpu: CPU) {
+        cpu.write_mem_u16(0x20, 0x0300);
+        cpu.write_mem(0x0600, 0x20);
+        cpu.program_counter = 0x0600;
+        cpu.index_register_y = 0x04;
+        assert_eq!(cpu.peek_operand_address(&AddressingMode::Indirect_Y), Some(0x0304));
+        assert_eq!(cpu.peek_operand_address(&AddressingMode::ZeroPage), Some(0x20));
+        assert_eq!(cpu.peek_operand_address(&AddressingMode::Immediate), None);
+        // The opcode at $05FF, as a tracer sees it before the instruction runs
+        assert_eq!(cpu.peek_operand_address_at(0x05FF, &AddressingMode::Indirect_Y), Some(0x0304));
+    }
+
     #[rstest]
     fn test_php(mut cpu: CPU) {
         cpu.load_and_execute(vec![0x08]);
@@ -736,7 +1999,8 @@ mod tests {
     #[rstest]
     fn test_plp(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0xFA, 0x48, 0x28]);
-        assert_eq!(cpu.status.status, 0xFA);
+        // B is dropped and bit 5 always reads as set
+        assert_eq!(cpu.status.status, 0xEA);
     }
 
     #[rstest]
@@ -744,8 +2008,8 @@ mod tests {
         cpu.load_and_execute(vec![
             0xA9, 0x81, 0x48, 0xA9, 0x02, 0x48, 0xA9, 0xFA, 0x48, 0x40,
         ]);
-        assert_eq!(cpu.status.status, 0xFA);
-        assert_eq!(cpu.program_counter, 0x0282)
+        assert_eq!(cpu.status.status, 0xEA);
+        assert_eq!(cpu.program_counter, 0x8103)
     }
 
     #[rstest]
@@ -783,13 +2047,14 @@ mod tests {
     fn test_bit(mut cpu: CPU) {
         cpu.write_mem(0x10, 0xFF);
         cpu.load_and_execute(vec![0xA9, 0x0, 0x24, 0x10]);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Overflow), false);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), false);
+        // N and V are copied from the operand even when the AND is zero
+        assert!(cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Overflow));
+        assert!(cpu.status.get_flag(StatusFlag::Negative));
         cpu.load_and_execute(vec![0xA9, 0b1100_0000, 0x24, 0x10]);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), false);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Overflow), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), true);
+        assert!(!cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Overflow));
+        assert!(cpu.status.get_flag(StatusFlag::Negative));
     }
 
     #[rstest]
@@ -797,8 +2062,8 @@ mod tests {
         cpu.load_program(vec![0x18]);
         cpu.reset();
         cpu.status.set_flag(StatusFlag::Carry, true);
-        cpu.execute();
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), false);
+        cpu.execute().unwrap();
+        assert!(!cpu.status.get_flag(StatusFlag::Carry));
     }
 
     #[rstest]
@@ -806,8 +2071,8 @@ mod tests {
         cpu.load_program(vec![0xD8]);
         cpu.reset();
         cpu.status.set_flag(StatusFlag::Decimal, true);
-        cpu.execute();
-        assert_eq!(cpu.status.get_flag(StatusFlag::Decimal), false);
+        cpu.execute().unwrap();
+        assert!(!cpu.status.get_flag(StatusFlag::Decimal));
     }
 
     #[rstest]
@@ -815,8 +2080,8 @@ mod tests {
         cpu.load_program(vec![0x58]);
         cpu.reset();
         cpu.status.set_flag(StatusFlag::InterruptDisable, true);
-        cpu.execute();
-        assert_eq!(cpu.status.get_flag(StatusFlag::InterruptDisable), false);
+        cpu.execute().unwrap();
+        assert!(!cpu.status.get_flag(StatusFlag::InterruptDisable));
     }
 
     #[rstest]
@@ -824,26 +2089,26 @@ mod tests {
         cpu.load_program(vec![0xB8]);
         cpu.reset();
         cpu.status.set_flag(StatusFlag::Overflow, true);
-        cpu.execute();
-        assert_eq!(cpu.status.get_flag(StatusFlag::Overflow), false);
+        cpu.execute().unwrap();
+        assert!(!cpu.status.get_flag(StatusFlag::Overflow));
     }
 
     #[rstest]
     fn test_cmp(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0x42, 0xC9, 0x42]);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), false);
+        assert!(cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+        assert!(!cpu.status.get_flag(StatusFlag::Negative));
 
         cpu.load_and_execute(vec![0xA9, 0x43, 0xC9, 0x42]);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), false);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), false);
+        assert!(!cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+        assert!(!cpu.status.get_flag(StatusFlag::Negative));
 
         cpu.load_and_execute(vec![0xA9, 0x42, 0xC9, 0xC2]);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), false);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), false);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), true);
+        assert!(!cpu.status.get_flag(StatusFlag::Zero));
+        assert!(!cpu.status.get_flag(StatusFlag::Carry));
+        assert!(cpu.status.get_flag(StatusFlag::Negative));
     }
 
     #[rstest]
@@ -857,8 +2122,8 @@ mod tests {
     fn test_eor(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0x10, 0x49, 0x10]);
         assert_eq!(cpu.register_accumulator, 0x00);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Zero), true);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Negative), false);
+        assert!(cpu.status.get_flag(StatusFlag::Zero));
+        assert!(!cpu.status.get_flag(StatusFlag::Negative));
     }
 
     #[rstest]
@@ -877,12 +2142,12 @@ mod tests {
         cpu.write_mem_u16(0xCAFE, 0xCADA);
         cpu.load_and_execute(vec![0x6C, 0xFE, 0xCA]);
         assert_eq!(cpu.program_counter, 0xCADB);
-        // Indirect with page boundary bug
-        cpu.write_mem(0x0000, 0x40);
-        cpu.write_mem(0x00FF, 0x50);
-        cpu.write_mem(0x0100, 0x30);
+        // Indirect with page boundary bug, landing on a BRK in RAM
+        cpu.write_mem(0x0000, 0x02);
+        cpu.write_mem(0x00FF, 0x00);
+        cpu.write_mem(0x0100, 0x03);
         cpu.load_and_execute(vec![0x6C, 0xFF, 0x00]);
-        assert_eq!(cpu.program_counter, 0x4051);
+        assert_eq!(cpu.program_counter, 0x0201);
     }
 
     #[rstest]
@@ -891,6 +2156,36 @@ mod tests {
         assert_eq!(cpu.stack_pull_u16(), 0xCAFE);
     }
 
+    #[rstest]
+    fn test_stack_wraparound(mut cpu: CPU) {
+        cpu.stack_pointer = 0x00;
+        cpu.stack_push(0x42);
+        assert_eq!(cpu.stack_pointer, 0xFF);
+        assert_eq!(cpu.read_mem(0x0100), 0x42);
+        assert_eq!(cpu.stack_pull(), 0x42);
+        assert_eq!(cpu.stack_pointer, 0x00);
+
+        // Underflow: PLA on an empty stack reads $0100
+        cpu.write_mem(0x0100, 0x17);
+        cpu.load_and_execute(vec![0x68, 0x00]);
+        assert_eq!(cpu.register_accumulator, 0x17);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[rstest]
+    fn test_deep_recursion(mut cpu: CPU) {
+        cpu.load_and_execute(vec![
+            0xA2, 0x90, // LDX #$90
+            0xCA, // DEX
+            0xF0, 0x03, // BEQ $8008
+            0x20, 0x02, 0x80, // JSR $8002
+            0x00, // BRK
+        ]);
+        // 143 nested calls push 286 bytes, wrapping the stack once
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(30));
+        assert_eq!(cpu.read_mem_u16(STACK + cpu.stack_pointer as u16 + 1), 0x8007);
+    }
+
     #[rstest]
     fn test_jsr(mut cpu: CPU) {
         cpu.load_and_execute(vec![0x20, 0xFD, 0xCA]);
@@ -898,6 +2193,31 @@ mod tests {
         assert_eq!(cpu.program_counter, 0xCAFE);
     }
 
+    #[rstest]
+    fn test_kil(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0x42, 0x02, 0xA9, 0x10]);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.register_accumulator, 0x42);
+        // A jammed CPU does not execute anything until reset
+        assert_eq!(cpu.step(), Err(CpuError::Jammed { address: 0x8002 }));
+        assert_eq!(cpu.execute(), Err(CpuError::Jammed { address: 0x8002 }));
+        assert_eq!(cpu.register_accumulator, 0x42);
+        cpu.reset();
+        assert!(!cpu.is_jammed());
+    }
+
+    #[rstest]
+    fn test_history(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0x42, 0xAA, 0x00]);
+        let history: Vec<&TraceEntry> = cpu.history().collect();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].program_counter, 0x8000);
+        assert_eq!(history[0].operands[0], 0x42);
+        assert_eq!(history[1].register_accumulator, 0x42);
+        assert_eq!(history[2].opcode, 0x00);
+    }
+
     #[rstest]
     fn test_ldx(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA2, 0x42]);
@@ -914,7 +2234,7 @@ mod tests {
     fn test_lsr(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0b1110_0011, 0x4A]);
         assert_eq!(cpu.register_accumulator, 0b0111_0001);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), true);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
     }
 
     #[rstest]
@@ -932,15 +2252,15 @@ mod tests {
     #[rstest]
     fn test_rol(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0b1000_0010, 0x2A]);
-        assert_eq!(cpu.register_accumulator, 0b_0000_0101);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), true);
+        assert_eq!(cpu.register_accumulator, 0b_0000_0100);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
     }
 
     #[rstest]
     fn test_ror(mut cpu: CPU) {
         cpu.load_and_execute(vec![0xA9, 0b1000_0011, 0x6A]);
-        assert_eq!(cpu.register_accumulator, 0b1100_0001);
-        assert_eq!(cpu.status.get_flag(StatusFlag::Carry), true);
+        assert_eq!(cpu.register_accumulator, 0b0100_0001);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
     }
 
     #[rstest]
@@ -997,4 +2317,741 @@ mod tests {
         cpu.load_and_execute(vec![0xA2, 0x42, 0x9A]);
         assert_eq!(cpu.stack_pointer, 0x42);
     }
+
+    #[rstest]
+    fn test_lax(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x85);
+        cpu.load_and_execute(vec![0xA7, 0x10]);
+        assert_eq!(cpu.register_accumulator, 0x85);
+        assert_eq!(cpu.index_register_x, 0x85);
+        assert!(cpu.status.get_flag(StatusFlag::Negative));
+    }
+
+    #[rstest]
+    fn test_sax(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0xF0, 0xA2, 0x3C, 0x87, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x30);
+    }
+
+    #[rstest]
+    fn test_dcp(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x43);
+        cpu.load_and_execute(vec![0xA9, 0x42, 0xC7, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x42);
+        assert!(cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_isb(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x40);
+        cpu.load_and_execute(vec![0x38, 0xA9, 0x50, 0xE7, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x41);
+        assert_eq!(cpu.register_accumulator, 0x0F);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_slo(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x81);
+        cpu.load_and_execute(vec![0xA9, 0x01, 0x07, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x02);
+        assert_eq!(cpu.register_accumulator, 0x03);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_rla(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x81);
+        cpu.load_and_execute(vec![0x38, 0xA9, 0x0F, 0x27, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x03);
+        assert_eq!(cpu.register_accumulator, 0x03);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_sre(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x03);
+        cpu.load_and_execute(vec![0xA9, 0x01, 0x47, 0x10]);
+        assert_eq!(cpu.read_mem(0x10), 0x01);
+        assert_eq!(cpu.register_accumulator, 0x00);
+        assert!(cpu.status.get_flag(StatusFlag::Zero));
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_rra(mut cpu: CPU) {
+        cpu.write_mem(0x10, 0x03);
+        cpu.load_and_execute(vec![0xA9, 0x10, 0x67, 0x10]);
+        // ROR leaves 0x01 with carry set, which the ADC then adds
+        assert_eq!(cpu.read_mem(0x10), 0x01);
+        assert_eq!(cpu.register_accumulator, 0x12);
+        assert!(!cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_anc(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0xFF, 0x0B, 0x80]);
+        assert_eq!(cpu.register_accumulator, 0x80);
+        assert!(cpu.status.get_flag(StatusFlag::Negative));
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_alr(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0xFF, 0x4B, 0x03]);
+        assert_eq!(cpu.register_accumulator, 0x01);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_arr(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0x38, 0xA9, 0xFF, 0x6B, 0xFF]);
+        assert_eq!(cpu.register_accumulator, 0xFF);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+        assert!(!cpu.status.get_flag(StatusFlag::Overflow));
+
+        cpu.load_and_execute(vec![0x18, 0xA9, 0xFF, 0x6B, 0x40]);
+        assert_eq!(cpu.register_accumulator, 0x20);
+        assert!(!cpu.status.get_flag(StatusFlag::Carry));
+        assert!(cpu.status.get_flag(StatusFlag::Overflow));
+    }
+
+    #[rstest]
+    fn test_axs(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0xFF, 0xA2, 0x0F, 0xCB, 0x05]);
+        assert_eq!(cpu.index_register_x, 0x0A);
+        assert!(cpu.status.get_flag(StatusFlag::Carry));
+    }
+
+    #[rstest]
+    fn test_unofficial_sbc(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0x38, 0xA9, 0x50, 0xEB, 0x10]);
+        assert_eq!(cpu.register_accumulator, 0x40);
+        assert!(!opcodes::CPU_OPCODES_MAP[&0xEB].official);
+    }
+
+    #[rstest]
+    fn test_unofficial_nops(mut cpu: CPU) {
+        cpu.load_program(vec![
+            0xA2, 0x01, // LDX #$01
+            0x1C, 0xFF, 0x80, // NOP $80FF,X: crosses a page
+            0x1C, 0x00, 0x80, // NOP $8000,X
+            0x04, 0x44, // NOP $44
+            0x80, 0x12, // NOP #$12
+            0x1A, // NOP
+            0xBD, 0xFF, 0x80, // LDA $80FF,X: crosses a page
+            0x9D, 0xFF, 0x80, // STA $80FF,X: no penalty for writes
+        ]);
+        cpu.reset();
+        let cycles: Vec<u16> = (0..8).map(|_| cpu.step().unwrap()).collect();
+        assert_eq!(cycles, vec![2, 5, 4, 3, 2, 2, 5, 5]);
+        assert_eq!(cpu.program_counter, 0x8013);
+        assert_eq!(cpu.register_accumulator, 0x00);
+        assert!(!opcodes::CPU_OPCODES_MAP[&0x1C].official);
+    }
+
+    #[rstest]
+    fn test_branch_cycles(mut cpu: CPU) {
+        let mut program = vec![
+            0xA9, 0x01, // LDA #$01
+            0xF0, 0x10, // BEQ $8014: not taken
+            0xD0, 0x00, // BNE $8006: taken
+            0x4C, 0xFC, 0x80, // JMP $80FC
+        ];
+        program.resize(0xFC, 0xEA);
+        program.extend([0xD0, 0x10]); // BNE $810E: taken to a new page
+        cpu.load_program(program);
+        cpu.reset();
+        cpu.reset_cycles();
+        let cycles: Vec<u16> = (0..5).map(|_| cpu.step().unwrap()).collect();
+        assert_eq!(cycles, vec![2, 2, 3, 3, 4]);
+        assert_eq!(cpu.program_counter, 0x810E);
+        assert_eq!(cpu.cycles, 14);
+    }
+
+    #[rstest]
+    fn test_indirect_x_pointer_wraps_in_zero_page(mut cpu: CPU) {
+        cpu.write_mem(0xFF, 0x34);
+        cpu.write_mem(0x00, 0x02);
+        cpu.write_mem(0x0100, 0x07);
+        cpu.write_mem(0x0234, 0x42);
+        // LDX #$01 / LDA ($FE,X)
+        cpu.load_and_execute(vec![0xA2, 0x01, 0xA1, 0xFE]);
+        assert_eq!(cpu.register_accumulator, 0x42);
+    }
+
+    #[rstest]
+    fn test_indirect_y_pointer_wraps_in_zero_page(mut cpu: CPU) {
+        cpu.write_mem(0xFF, 0x30);
+        cpu.write_mem(0x00, 0x02);
+        cpu.write_mem(0x0100, 0x07);
+        cpu.write_mem(0x0234, 0x42);
+        // LDY #$04 / LDA ($FF),Y
+        cpu.load_and_execute(vec![0xA0, 0x04, 0xB1, 0xFF]);
+        assert_eq!(cpu.register_accumulator, 0x42);
+    }
+
+    #[rstest]
+    fn test_read_u16_pagewrap(mut cpu: CPU) {
+        cpu.write_mem(0x02FF, 0x34);
+        cpu.write_mem(0x0300, 0x56);
+        cpu.write_mem(0x0200, 0x12);
+        assert_eq!(cpu.read_u16_pagewrap(0x02FF, MemAccess::Read), 0x1234);
+        assert_eq!(cpu.read_u16_pagewrap(0x02FE, MemAccess::Read), 0x3400 | cpu.read_mem(0x02FE) as u16);
+        cpu.write_mem(0xFF, 0xCD);
+        cpu.write_mem(0x00, 0xAB);
+        assert_eq!(cpu.read_zp_u16(0xFF, MemAccess::Read), 0xABCD);
+    }
+
+    #[rstest]
+    fn test_nmi(mut cpu: CPU) {
+        // NOP at $8000, BRK in the handler at $9000
+        cpu.load_program(vec![0xEA]);
+        cpu.write_mem(0x9000, 0x00);
+        cpu.write_mem_u16(0xFFFA, 0x9000);
+        cpu.reset();
+        cpu.status.set_flag(StatusFlag::Carry, true);
+        cpu.bus.trigger_nmi();
+        cpu.bus.reset_stats();
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x9001);
+        assert!(!cpu.bus.nmi_pending());
+        assert!(cpu.status.get_flag(StatusFlag::InterruptDisable));
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8000);
+        assert_eq!(cpu.read_mem(0x01FD), 0b0010_0001);
+        // 7 interrupt cycles, then the BRK fetch
+        assert_eq!(cpu.bus.stats().total(), 8);
+    }
+
+    #[rstest]
+    fn test_brk_interrupt(mut cpu: CPU) {
+        // BRK at $8000, KIL in the handler at $9000
+        cpu.load_program(vec![0x00, 0xFF]);
+        cpu.write_mem(0x9000, 0x02);
+        cpu.write_mem_u16(0xFFFE, 0x9000);
+        cpu.reset();
+        cpu.set_halt_on_brk(false);
+        assert_eq!(cpu.execute(), Err(CpuError::Jammed { address: 0x9000 }));
+
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert!(cpu.status.get_flag(StatusFlag::InterruptDisable));
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8002);
+        assert_eq!(cpu.read_mem(0x01FD), 0b0011_0000);
+    }
+
+    #[rstest]
+    fn test_irq(mut cpu: CPU) {
+        // NOP at $8000, BRK in the handler at $9000
+        cpu.load_program(vec![0xEA]);
+        cpu.write_mem(0x9000, 0x00);
+        cpu.write_mem_u16(0xFFFE, 0x9000);
+        cpu.reset();
+        cpu.bus.assert_irq(IrqSource::Mapper);
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x9001);
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8000);
+        assert_eq!(cpu.read_mem(0x01FD), 0b0010_0000);
+    }
+
+    #[rstest]
+    fn test_irq_masked_by_interrupt_disable(mut cpu: CPU) {
+        cpu.load_program(vec![0xEA]);
+        cpu.reset();
+        cpu.status.set_flag(StatusFlag::InterruptDisable, true);
+        cpu.bus.assert_irq(IrqSource::Mapper);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[rstest]
+    fn test_step(mut cpu: CPU) {
+        // LDA #$42 / STA $0300 / unstable XAA
+        cpu.load_program(vec![0xA9, 0x42, 0x8D, 0x00, 0x03, 0x8B]);
+        cpu.reset();
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.register_accumulator, 0x42);
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.read_mem(0x0300), 0x42);
+        assert_eq!(cpu.step(), Err(CpuError::UnknownOpcode { opcode: 0x8B, address: 0x8005 }));
+
+        // Servicing an NMI adds the 7 interrupt cycles
+        cpu.write_mem(0x9000, 0xEA);
+        cpu.write_mem_u16(0xFFFA, 0x9000);
+        cpu.bus.trigger_nmi();
+        assert_eq!(cpu.step(), Ok(9));
+        assert_eq!(cpu.program_counter, 0x9001);
+    }
+
+    #[rstest]
+    fn test_unknown_opcode_after_nmi(mut cpu: CPU) {
+        // NMI handler: unstable XAA / INX
+        cpu.load_program(vec![0xEA]);
+        cpu.reset();
+        cpu.write_mem(0x9000, 0x8B);
+        cpu.write_mem(0x9001, 0xE8);
+        cpu.write_mem_u16(0xFFFA, 0x9000);
+        cpu.bus.trigger_nmi();
+        cpu.stall(3);
+        assert_eq!(cpu.step(), Err(CpuError::UnknownOpcode { opcode: 0x8B, address: 0x9000 }));
+        // The stall and the NMI happened
+        assert_eq!(cpu.cycles, 3 + INTERRUPT_CYCLES as u64);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+
+        // Skipping the byte resumes in the handler, without another NMI
+        cpu.program_counter += 1;
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.index_register_x, 1);
+        assert_eq!(cpu.cycles, 3 + INTERRUPT_CYCLES as u64 + 2);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+    }
+
+    #[rstest]
+    fn test_instructions(mut cpu: CPU) {
+        // LDX #$02 / loop: DEX / BNE loop / BRK
+        cpu.load_program(vec![0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0x00]);
+        cpu.reset();
+        let records: Vec<InstructionRecord> = cpu.instructions().map(Result::unwrap).collect();
+        let labels: Vec<&str> = records.iter().map(|r| r.opcode.label).collect();
+        assert_eq!(labels, vec!["LDX", "DEX", "BNE", "DEX", "BNE", "BRK"]);
+        assert_eq!(records[3].before.program_counter, 0x8002);
+        assert_eq!(records[3].before.index_register_x, 0x01);
+        assert_eq!(records[0].cycles, 2);
+    }
+
+    #[rstest]
+    fn test_trace_entry_display(mut cpu: CPU) {
+        cpu.load_and_execute(vec![0xA9, 0x42, 0x8D, 0x00, 0x03]);
+        let history: Vec<String> = cpu.history().map(|entry| entry.to_string()).collect();
+        assert_eq!(history[0], "$8000  A9 42     LDA  A:00 X:00 Y:00 P:nv-bdizc SP:FF");
+        assert_eq!(history[1], "$8002  8D 00 03  STA  A:42 X:00 Y:00 P:nv-bdizc SP:FF");
+    }
+
+    #[rstest]
+    fn test_execute_errors(mut cpu: CPU) {
+        // NOP / unstable XAA / BRK
+        cpu.load_program(vec![0xEA, 0x8B, 0x00]);
+        cpu.reset();
+        assert_eq!(cpu.execute(), Err(CpuError::UnknownOpcode { opcode: 0x8B, address: 0x8001 }));
+        assert_eq!(cpu.program_counter, 0x8001);
+        // Skipping the byte resumes execution
+        cpu.program_counter += 1;
+        assert_eq!(cpu.execute(), Ok(StopReason::Brk));
+    }
+
+    #[rstest]
+    fn test_breakpoints(mut cpu: CPU) {
+        // INX / INX / INX / BRK
+        cpu.load_program(vec![0xE8, 0xE8, 0xE8, 0x00]);
+        cpu.reset();
+        assert!(cpu.add_breakpoint(0x8002));
+        assert!(cpu.add_breakpoint(0x8001));
+        assert!(!cpu.add_breakpoint(0x8001));
+        assert_eq!(cpu.breakpoints().collect::<Vec<_>>(), vec![0x8001, 0x8002]);
+
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8001)));
+        assert_eq!(cpu.index_register_x, 1);
+        // Executing again resumes past the breakpoint
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8002)));
+        assert_eq!(cpu.index_register_x, 2);
+        // So does stepping
+        cpu.step().unwrap();
+        assert_eq!(cpu.index_register_x, 3);
+
+        assert!(cpu.remove_breakpoint(0x8001));
+        assert!(!cpu.remove_breakpoint(0x8001));
+        cpu.reset();
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8002)));
+        assert_eq!(cpu.execute(), Ok(StopReason::Brk));
+    }
+
+    #[rstest]
+    fn test_run_until(mut cpu: CPU) {
+        cpu.load_program(vec![
+            0xA2, 0x00, // LDX #$00
+            0xE8, // INX
+            0x86, 0x10, // STX $10
+            0x4C, 0x02, 0x80, // JMP $8002
+        ]);
+        cpu.reset();
+        assert_eq!(cpu.run_until(|cpu| cpu.program_counter == 0x8005), Ok(StopReason::Condition));
+        assert_eq!(cpu.index_register_x, 1);
+        // Already true, nothing runs
+        assert_eq!(cpu.run_until(|cpu| cpu.program_counter == 0x8005), Ok(StopReason::Condition));
+        assert_eq!(cpu.index_register_x, 1);
+
+        assert_eq!(cpu.run_until(|cpu| cpu.read_mem(0x10) == 5), Ok(StopReason::Condition));
+        assert_eq!(cpu.program_counter, 0x8005);
+        let end = cpu.cycles + 100;
+        assert_eq!(cpu.run_until(|cpu| cpu.cycles >= end), Ok(StopReason::Condition));
+        assert!(cpu.cycles < end + 3);
+
+        // BRK still stops the run
+        cpu.load_program(vec![0xE8, 0x00]);
+        cpu.reset();
+        assert_eq!(cpu.run_until(|cpu| cpu.index_register_x == 2), Ok(StopReason::Brk));
+    }
+
+    #[rstest]
+    fn test_watchpoints(mut cpu: CPU) {
+        // LDA $10 / STA $0205 / LDA #$01 / STA $0300 / BRK
+        cpu.load_program(vec![0xA5, 0x10, 0x8D, 0x05, 0x02, 0xA9, 0x01, 0x8D, 0x00, 0x03, 0x00]);
+        cpu.reset();
+        cpu.write_mem(0x10, 0x42);
+        cpu.add_watchpoint(Watchpoint { start: 0x0200, end: 0x02FF, kind: WatchKind::Write });
+        cpu.add_watchpoint(Watchpoint { start: 0x10, end: 0x10, kind: WatchKind::Read });
+        // Watching reads doesn't catch the opcode fetches
+        cpu.add_watchpoint(Watchpoint { start: 0x8005, end: 0x8005, kind: WatchKind::Read });
+
+        let hit = WatchHit { program_counter: 0x8000, address: 0x10, value: 0x42, write: false };
+        assert_eq!(cpu.execute(), Ok(StopReason::Watchpoint(hit)));
+        assert_eq!(cpu.program_counter, 0x8002);
+        let hit = WatchHit { program_counter: 0x8002, address: 0x0205, value: 0x42, write: true };
+        assert_eq!(cpu.execute(), Ok(StopReason::Watchpoint(hit)));
+        assert_eq!(hit.to_string(), "Write $42 at $0205 by the instruction at $8002");
+        assert_eq!(cpu.execute(), Ok(StopReason::Brk));
+        assert_eq!(cpu.read_mem(0x0300), 0x01);
+    }
+
+    #[rstest]
+    fn test_cycles(mut cpu: CPU) {
+        // LDA #$01 / STA $0200 / BRK
+        cpu.load_program(vec![0xA9, 0x01, 0x8D, 0x00, 0x02, 0x00]);
+        cpu.reset();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.cycles, 2 + 4 + 7);
+        cpu.reset_cycles();
+        assert_eq!(cpu.cycles, 0);
+    }
+
+    #[rstest]
+    fn test_oam_dma_stall(mut cpu: CPU) {
+        // LDA #$02 / STA $4014 / NOP
+        cpu.load_program(vec![0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA]);
+        cpu.reset();
+        cpu.step().unwrap();
+        // The write ends on cycle 6, DMA starts on an even cycle
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.step(), Ok(513 + 2));
+        assert_eq!(cpu.cycles, 2 + 4 + 513 + 2);
+
+        // Starting one cycle later adds an alignment cycle
+        cpu.reset();
+        cpu.reset_cycles();
+        cpu.stall(1);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Ok(514 + 2));
+    }
+
+    #[rstest]
+    fn test_dmc_dma_stall(mut cpu: CPU) {
+        // NOP / NOP, then a sample byte
+        cpu.load_program(vec![0xEA, 0xEA, 0x2A]);
+        cpu.reset();
+        cpu.bus.request_dmc_dma(0x8002);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.take_dmc_sample(), Some(0x2A));
+        assert_eq!(cpu.bus.take_dmc_sample(), None);
+        assert_eq!(cpu.step(), Ok(4 + 2));
+    }
+
+    #[rstest]
+    fn test_page_crossing_dummy_reads(mut cpu: CPU) {
+        // LDA $40F0,X / LDA ($10),Y / LDA $0200,X
+        cpu.load_program(vec![0xBD, 0xF0, 0x40, 0xB1, 0x10, 0xBD, 0x00, 0x02, 0x00]);
+        cpu.reset();
+        cpu.write_mem_u16(0x10, 0x02F0);
+        cpu.index_register_x = 0x20;
+        cpu.index_register_y = 0x20;
+        cpu.set_trace(false);
+        cpu.set_cycle_accurate(true);
+        cpu.add_watchpoint(Watchpoint { start: 0x4000, end: 0x4017, kind: WatchKind::Read });
+        cpu.add_watchpoint(Watchpoint { start: 0x0210, end: 0x0210, kind: WatchKind::Read });
+        cpu.write_mem(0x0210, 0x55);
+
+        // $40F0+$20 reads $4010 before $4110, write-only: the operand's high
+        // byte is still on the bus
+        let hit = WatchHit { program_counter: 0x8000, address: 0x4010, value: 0x40, write: false };
+        assert_eq!(cpu.execute(), Ok(StopReason::Watchpoint(hit)));
+        // $02F0+$20 reads $0210 before $0310
+        let hit = WatchHit { program_counter: 0x8003, address: 0x0210, value: 0x55, write: false };
+        assert_eq!(cpu.execute(), Ok(StopReason::Watchpoint(hit)));
+        // No page crossed, no dummy read
+        assert_eq!(cpu.execute(), Ok(StopReason::Brk));
+    }
+
+    #[rstest]
+    fn test_interrupt_polling_delays(mut cpu: CPU) {
+        // CLI / NOP / NOP, IRQ handler at $9000
+        cpu.load_program(vec![0x58, 0xEA, 0xEA]);
+        cpu.write_mem_u16(0xFFFE, 0x9000);
+        cpu.reset();
+        cpu.status.set_flag(StatusFlag::InterruptDisable, true);
+        cpu.bus.assert_irq(IrqSource::Mapper);
+        cpu.step().unwrap();
+        // The NOP after CLI still runs before the IRQ
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8002);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8002);
+
+        // SEI: the IRQ still gets in right after it
+        cpu.bus.acknowledge_irq(IrqSource::Mapper);
+        cpu.load_program(vec![0x78, 0xEA]);
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.bus.assert_irq(IrqSource::Mapper);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8001);
+        cpu.bus.acknowledge_irq(IrqSource::Mapper);
+
+        // BNE +0 / NOP: a taken branch in the same page delays the NMI
+        cpu.load_program(vec![0xD0, 0x00, 0xEA, 0xEA]);
+        cpu.write_mem_u16(0xFFFA, 0x9000);
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.bus.trigger_nmi();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8003);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_mem_u16(0x01FE), 0x8003);
+    }
+
+    // The CPU only needs a Mem: a bare 64KB array runs programs too
+    struct FlatRam(Vec<u8>);
+
+    impl Mem for FlatRam {
+        fn read_mem(&self, addr: u16) -> u8 {
+            self.0[addr as usize]
+        }
+
+        fn write_mem(&mut self, addr: u16, value: u8) {
+            self.0[addr as usize] = value;
+        }
+    }
+
+    #[test]
+    fn test_custom_memory() {
+        let mut cpu = CPU::new(FlatRam(vec![0; 0x10000]));
+        // LDA #$42 / INC $10 / STA $2000 / BRK
+        cpu.load_and_execute(vec![0xA9, 0x42, 0xE6, 0x10, 0x8D, 0x00, 0x20, 0x00]);
+        assert_eq!(cpu.register_accumulator, 0x42);
+        assert_eq!(cpu.bus.0[0x10], 1);
+        assert_eq!(cpu.bus.0[0x2000], 0x42);
+        assert_eq!(cpu.bus.describe_address(0x2000), "$2000");
+    }
+
+    #[rstest]
+    fn test_state(mut cpu: CPU) {
+        // LDX #$05 / SEI / DEX / BNE -3
+        cpu.load_program(vec![0xA2, 0x05, 0x78, 0xCA, 0xD0, 0xFD]);
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let saved = cpu.state();
+        assert!(saved.delayed_interrupt_disable.is_some());
+        let run = |cpu: &mut CPU| {
+            for _ in 0..4 {
+                cpu.step().unwrap();
+            }
+            cpu.state()
+        };
+        let first = run(&mut cpu);
+        cpu.set_state(&saved);
+        assert_eq!(cpu.state(), saved);
+        assert_eq!(run(&mut cpu), first);
+    }
+
+    #[rstest]
+    fn test_step_back(mut cpu: CPU) {
+        cpu.load_program(vec![
+            0xA9, 0x11, // LDA #$11
+            0x85, 0x10, // STA $10
+            0x20, 0x09, 0x80, // JSR $8009
+            0x00, 0x00, // BRK
+            0xE6, 0x10, // INC $10
+            0x60, // RTS
+        ]);
+        cpu.reset();
+        assert!(!cpu.step_back());
+        cpu.set_undo_depth(8);
+
+        let mut before = Vec::new();
+        for _ in 0..5 {
+            before.push((cpu.state(), cpu.read_mem(0x10), cpu.read_mem_u16(0x01FE), cpu.call_stack().len()));
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.read_mem(0x10), 0x12);
+        assert_eq!(cpu.undo_len(), 5);
+        while let Some(expected) = before.pop() {
+            assert!(cpu.step_back());
+            let now = (cpu.state(), cpu.read_mem(0x10), cpu.read_mem_u16(0x01FE), cpu.call_stack().len());
+            assert_eq!(now, expected);
+        }
+        assert!(!cpu.step_back());
+        assert_eq!(cpu.history().count(), 0);
+
+        // Only the last `depth` steps are kept
+        cpu.set_undo_depth(2);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.undo_len(), 2);
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert!(!cpu.step_back());
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    #[rstest]
+    fn test_snapshot(mut cpu: CPU) {
+        // LDA #$42 / LDX #$FF
+        cpu.load_program(vec![0xA9, 0x42, 0xA2, 0xFF]);
+        cpu.reset();
+        cpu.reset_cycles();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.pc, 0x8004);
+        assert_eq!((snapshot.a, snapshot.x, snapshot.y), (0x42, 0xFF, 0x00));
+        assert_eq!(snapshot.p, cpu.status.status);
+        assert_eq!(snapshot.to_string(), format!("PC:8004 A:42 X:FF Y:00 P:{:02X} SP:FF CYC:4", snapshot.p));
+    }
+
+    #[rstest]
+    fn test_profiling(mut cpu: CPU) {
+        assert!(cpu.profile().is_none());
+        cpu.start_profiling(true);
+        // LDX #$03 / DEX / BNE -3 / BRK
+        cpu.load_and_execute(vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x00]);
+        let profile = cpu.profile().unwrap();
+        assert_eq!(profile.opcode(0xCA).executions, 3);
+        assert_eq!(profile.opcode(0xA2).cycles, 2);
+        assert_eq!(profile.address(0x8003).unwrap().executions, 3);
+        assert_eq!(profile.total().executions, 8);
+
+        cpu.reset_profile();
+        assert_eq!(cpu.profile().unwrap().total().executions, 0);
+        assert!(cpu.stop_profiling().is_some());
+        assert!(cpu.profile().is_none());
+    }
+
+    #[rstest]
+    fn test_call_stack(mut cpu: CPU) {
+        let mut program = vec![0; 0x23];
+        program[0x00..0x03].copy_from_slice(&[0x20, 0x10, 0x80]); // JSR $8010
+        program[0x10..0x14].copy_from_slice(&[0x20, 0x20, 0x80, 0x60]); // JSR $8020 / RTS
+        // PLA / PLA / RTS: drops its return address and returns to the outer caller
+        program[0x20..0x23].copy_from_slice(&[0x68, 0x68, 0x60]);
+        cpu.load_program(program);
+        cpu.reset();
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let entries: Vec<(u16, u16)> = cpu.call_stack().iter().map(|f| (f.entry, f.return_address)).collect();
+        assert_eq!(entries, vec![(0x8010, 0x8003), (0x8020, 0x8013)]);
+        assert_eq!(cpu.call_stack()[1].to_string(), "JSR $8020, returns to $8013");
+        cpu.step().unwrap();
+        assert_eq!(cpu.call_stack().len(), 2);
+        cpu.step().unwrap();
+        assert_eq!(cpu.call_stack().len(), 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8003);
+        assert!(cpu.call_stack().is_empty());
+
+        cpu.write_mem_u16(0xFFFA, 0x8010);
+        cpu.bus.trigger_nmi();
+        cpu.step().unwrap();
+        assert_eq!(cpu.call_stack()[0].kind, CallKind::Nmi);
+        assert_eq!(cpu.call_stack()[0].return_address, 0x8003);
+        cpu.reset();
+        assert!(cpu.call_stack().is_empty());
+    }
+
+    #[rstest]
+    fn test_power_on(mut cpu: CPU) {
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+        assert!(!cpu.status.get_flag(StatusFlag::InterruptDisable));
+
+        cpu.power_on(PowerOnConfig::hardware(42));
+        let ram: Vec<u8> = (0..0x0800).map(|addr| cpu.read_mem(addr)).collect();
+        assert!(ram.iter().any(|&byte| byte != 0));
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, 0xFD);
+        assert!(cpu.status.get_flag(StatusFlag::InterruptDisable));
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, 0xFA);
+
+        // The same seed gives the same RAM
+        let mut other = CPU::new(Bus::new(ROM::empty()));
+        other.power_on(PowerOnConfig::hardware(42));
+        assert!((0..0x0800).all(|addr| other.read_mem(addr) == ram[addr as usize]));
+    }
+
+    #[rstest]
+    fn test_stop_from_another_thread(mut cpu: CPU) {
+        let control = cpu.control();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            control.stop();
+        });
+        cpu.set_trace(false);
+        // JMP $8000, forever
+        cpu.load_program(vec![0x4C, 0x00, 0x80]);
+        cpu.reset();
+        assert_eq!(cpu.execute(), Ok(StopReason::Stopped));
+        thread.join().unwrap();
+
+        // Stopped from the callback, before the next instruction
+        let control = cpu.control();
+        let mut steps = 0;
+        let stop = cpu.execute_with_callback(|_| {
+            steps += 1;
+            if steps == 3 {
+                control.stop();
+            }
+        });
+        assert_eq!((stop, steps), (Ok(StopReason::Stopped), 3));
+    }
+
+    #[rstest]
+    fn test_hooks(mut cpu: CPU) {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        let seen = Rc::new(RefCell::new(vec![]));
+        let log = seen.clone();
+        let before = cpu.add_hook(HookPoint::Before, move |_, event| {
+            log.borrow_mut().push((event.address, event.opcode.label, event.operand_address, event.state.index_register_x));
+        });
+        // A cheat: $10 is always 99 after an instruction
+        let cheat = cpu.add_hook(HookPoint::After, |cpu, _| cpu.write_mem(0x10, 99));
+        // LDX #$05 / STX $10 / LDA $10 / BRK
+        cpu.load_and_execute(vec![0xA2, 0x05, 0x86, 0x10, 0xA5, 0x10, 0x00]);
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                (0x8000, "LDX", None, 0),
+                (0x8002, "STX", Some(0x10), 5),
+                (0x8004, "LDA", Some(0x10), 5),
+                (0x8006, "BRK", None, 5),
+            ]
+        );
+        assert_eq!(cpu.register_accumulator, 99);
+
+        assert!(cpu.remove_hook(before));
+        assert!(cpu.remove_hook(cheat));
+        assert!(!cpu.remove_hook(cheat));
+        cpu.load_and_execute(vec![0xA2, 0x05, 0x86, 0x10, 0xA5, 0x10, 0x00]);
+        assert_eq!(cpu.register_accumulator, 5);
+        assert_eq!(seen.borrow().len(), 4);
+    }
 }
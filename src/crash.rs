@@ -0,0 +1,87 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cpu::CPU;
+use crate::opcodes::CPU_OPCODES_MAP;
+
+// Human readable report of the CPU state, attached to bug reports
+pub fn crash_report(cpu: &CPU, reason: &str) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "{} {} crash report", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Reason: {}", reason);
+
+    if let Some(last) = cpu.history().last() {
+        let _ = writeln!(report, "Offending address: {:#06X}", last.program_counter);
+    }
+    let _ = writeln!(report, "Registers: {}", cpu.snapshot());
+    match cpu.bus.rom() {
+        Some(rom) => {
+            let _ = writeln!(
+                report,
+                "ROM: mapper {}, PRG {}KB, CHR {}KB, {:?} mirroring",
+                rom.mapper(),
+                rom.prg_rom.len() / 1024,
+                rom.chr_rom.len() / 1024,
+                rom.screen_mirroring(),
+            );
+        }
+        None => {
+            let _ = writeln!(report, "ROM: none");
+        }
+    }
+
+    let _ = writeln!(report, "\nLast {} instructions:", cpu.history().count());
+    for entry in cpu.history() {
+        let (label, operands) = match CPU_OPCODES_MAP.get(&entry.opcode) {
+            Some(opcode) => (opcode.label, &entry.operands[..opcode.bytes as usize - 1]),
+            None => ("???", &entry.operands[..0]),
+        };
+        let mut bytes = format!("{:02X}", entry.opcode);
+        for operand in operands {
+            let _ = write!(bytes, " {:02X}", operand);
+        }
+        let _ = writeln!(
+            report,
+            "{:04X}  {:<8}  {}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            entry.program_counter,
+            bytes,
+            label,
+            entry.register_accumulator,
+            entry.index_register_x,
+            entry.index_register_y,
+            entry.status,
+            entry.stack_pointer,
+        );
+    }
+    report
+}
+
+// Writes the crash report to the temp directory and returns its path
+pub fn write_crash_dump(cpu: &CPU, reason: &str) -> Result<PathBuf, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = std::env::temp_dir().join(format!("nes-crash-{}.txt", timestamp));
+    std::fs::write(&path, crash_report(cpu, reason)).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::ROM;
+
+    #[test]
+    fn test_crash_report() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        cpu.load_and_execute(vec![0xA9, 0x42, 0x02]);
+        let report = crash_report(&cpu, "CPU jammed");
+        assert!(report.contains("Reason: CPU jammed"));
+        assert!(report.contains("Offending address: 0x8002"));
+        assert!(report.contains("8000  A9 42     LDA  A:00"));
+        assert!(report.contains("8002  02        KIL  A:42"));
+    }
+}
@@ -0,0 +1,681 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use core::fmt;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::bus::REGIONS;
+use crate::cpu::{Mem, WatchKind, Watchpoint, CPU};
+
+pub const HELP: &str = "Commands:
+  peek <addr>            read a byte
+  poke <addr> <byte>     write a byte
+  pokew <addr> <word>    write a little-endian word
+  freeze <addr> <byte>   write a byte before every instruction
+  unfreeze <addr>        stop writing a frozen byte
+  freezes                list frozen addresses
+  break [<bank>:]<addr>  stop before the instruction at addr, optionally
+                         only when PRG bank <bank> is mapped there
+  delete [<bank>:]<addr> remove a breakpoint
+  breakpoints            list breakpoints
+  watch <range> [r|w|rw] stop after an instruction reads and/or writes an
+                         address in range (\"$2002\", \"$0200..$02FF\"),
+                         default rw
+  unwatch <range> [r|w|rw]
+                         remove a watchpoint
+  watchpoints            list watchpoints
+  to <addr>              resume until the instruction at addr
+  finish                 resume until the current subroutine returns
+  frame                  resume until the next NMI handler (VBlank)
+  back [<count>]         undo the last instructions, one by default
+  undo <depth>           how many instructions back can undo, 0 turns it
+                         off
+  history                list previous commands
+  calls                  subroutines and interrupt handlers being run,
+                         innermost first
+  io                     readable PPU/APU registers, peeked without side
+                         effects
+  stats                  CPU reads/writes per memory region
+  resetstats             clear the memory traffic counters
+Numbers are decimal, $hex, 0xhex or %binary, and can be added or subtracted
+(\"poke $0300+2 $FF-1\").";
+
+// Instructions 'back' can undo in a console session, see Debugger::attach()
+pub const DEFAULT_UNDO_DEPTH: usize = 1000;
+
+// Errors out when there's no device behind `addr` to take a write
+fn check_writable(cpu: &CPU, addr: u16) -> Result<(), String> {
+    if !cpu.bus.is_writable(addr) {
+        return Err(format!("{}: no device attached to write to", cpu.bus.describe_address(addr)));
+    }
+    Ok(())
+}
+
+// Evaluates a numeric expression like "$07DD", "0x63", "%1010" or "$0200+32-1"
+pub fn eval_expr(expr: &str) -> Result<u16, String> {
+    let invalid = || format!("Invalid expression: {}", expr);
+    let mut total: i32 = 0;
+    let mut sign = 1;
+    let mut rest = expr.trim();
+    if rest.is_empty() || !rest.is_ascii() {
+        return Err(invalid());
+    }
+    loop {
+        let end = rest[1..].find(['+', '-']).map_or(rest.len(), |i| i + 1);
+        let term = rest[..end].trim();
+        let value = if let Some(hex) = term.strip_prefix('$').or_else(|| term.strip_prefix("0x")) {
+            i32::from_str_radix(hex, 16)
+        } else if let Some(bin) = term.strip_prefix('%') {
+            i32::from_str_radix(bin, 2)
+        } else {
+            term.parse()
+        }
+        .map_err(|_| invalid())?;
+        total += sign * value;
+
+        rest = &rest[end..];
+        sign = match rest.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        rest = rest[1..].trim_start();
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+    }
+    u16::try_from(total).map_err(|_| format!("Value out of range: {}", expr))
+}
+
+fn eval_byte(expr: &str) -> Result<u8, String> {
+    u8::try_from(eval_expr(expr)?).map_err(|_| format!("Not a byte: {}", expr))
+}
+
+// Stops before the instruction at `address`. With a bank, only when that PRG
+// bank is the one mapped there, since bank switching reuses CPU addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Breakpoint {
+    pub bank: Option<usize>,
+    pub address: u16,
+}
+
+impl Breakpoint {
+    // "$A012" or "3:$A012"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once(':') {
+            Some((bank, address)) => Ok(Self {
+                bank: Some(eval_expr(bank)? as usize),
+                address: eval_expr(address)?,
+            }),
+            None => Ok(Self { bank: None, address: eval_expr(spec)? }),
+        }
+    }
+
+    pub fn is_hit(&self, cpu: &CPU) -> bool {
+        let pc = cpu.program_counter;
+        pc == self.address
+            && self
+                .bank
+                .is_none_or(|bank| cpu.bus.prg_bank(pc).is_some_and(|(mapped, _)| mapped == bank))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{}:${:04X}", bank, self.address),
+            None => write!(f, "${:04X}", self.address),
+        }
+    }
+}
+
+// "<range> [r|w|rw]", the range being one address or "start..end"
+fn parse_watchpoint(range: &str, kind: Option<&str>) -> Result<Watchpoint, String> {
+    let (start, end) = match range.split_once("..") {
+        Some((start, end)) => (eval_expr(start)?, eval_expr(end)?),
+        None => (eval_expr(range)?, eval_expr(range)?),
+    };
+    if end < start {
+        return Err(format!("Invalid range: {}", range));
+    }
+    let kind = match kind {
+        Some("r") => WatchKind::Read,
+        Some("w") => WatchKind::Write,
+        Some("rw") | None => WatchKind::ReadWrite,
+        Some(kind) => return Err(format!("Invalid watch kind: {} (r, w or rw)", kind)),
+    };
+    Ok(Watchpoint { start, end, kind })
+}
+
+// Where a resumed run should stop again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunTo {
+    Address(u16),
+    // Until something pops above the stack pointer the command was given
+    // at, i.e. the RTS/RTI leaving the current subroutine
+    Return { stack_pointer: u8 },
+    // NMI handler entry, taken from the vector when the command is given
+    Frame { handler: u16 },
+}
+
+// Command interpreter for poking at a running machine. Writes go through the
+// CPU bus, writes to a device that isn't attached (like the PPU in 'nes run')
+// are rejected.
+#[derive(Default)]
+pub struct Debugger {
+    freezes: BTreeMap<u16, u8>,
+    breakpoints: BTreeSet<Breakpoint>,
+    run_to: Option<RunTo>,
+    history: Vec<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Sets the CPU up for a console session: keeps the last
+    // DEFAULT_UNDO_DEPTH instructions for 'back'
+    pub fn attach(&self, cpu: &mut CPU) {
+        cpu.set_undo_depth(DEFAULT_UNDO_DEPTH);
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn freezes(&self) -> &BTreeMap<u16, u8> {
+        &self.freezes
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<Breakpoint> {
+        &self.breakpoints
+    }
+
+    // The breakpoint stopping the instruction about to run, if any
+    pub fn breakpoint_hit(&self, cpu: &CPU) -> Option<Breakpoint> {
+        self.breakpoints.iter().find(|bp| bp.is_hit(cpu)).copied()
+    }
+
+    // Breakpoints, CPU watchpoints and freezes as console commands, one per
+    // line, so restore() can replay them in a later run
+    pub fn save(&self, cpu: &CPU) -> String {
+        let breaks = self.breakpoints.iter().map(|bp| format!("break {}", bp));
+        let watches = cpu.watchpoints().map(|w| {
+            let kind = match w.kind {
+                WatchKind::Read => "r",
+                WatchKind::Write => "w",
+                WatchKind::ReadWrite => "rw",
+            };
+            format!("watch ${:04X}..${:04X} {}", w.start, w.end, kind)
+        });
+        let freezes = self
+            .freezes
+            .iter()
+            .map(|(addr, value)| format!("freeze ${:04X} ${:02X}", addr, value));
+        breaks.chain(watches).chain(freezes).collect::<Vec<String>>().join("\n")
+    }
+
+    // Replays what save() produced. The commands don't go in the history.
+    // Lines that fail, e.g. a freeze on a device that isn't attached anymore,
+    // are skipped and reported together
+    pub fn restore(&mut self, cpu: &mut CPU, saved: &str) -> Result<(), String> {
+        let history = self.history.len();
+        let errors: Vec<String> = saved
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| self.execute(cpu, line).err().map(|e| format!("{}: {}", line.trim(), e)))
+            .collect();
+        self.history.truncate(history);
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    // True after a to/finish/frame command, the console should resume
+    pub fn is_running_to(&self) -> bool {
+        self.run_to.is_some()
+    }
+
+    // Why execution should stop before the instruction about to run, if it
+    // should: the end of a to/finish/frame run
+    pub fn stop_reason(&mut self, cpu: &CPU) -> Option<String> {
+        let pc = cpu.program_counter;
+        let reason = match self.run_to? {
+            RunTo::Address(address) if pc == address => format!("Reached ${:04X}", pc),
+            RunTo::Return { stack_pointer } if cpu.stack_pointer > stack_pointer => {
+                format!("Returned to ${:04X}", pc)
+            }
+            RunTo::Frame { handler } if pc == handler => format!("NMI at ${:04X}", pc),
+            _ => return None,
+        };
+        self.run_to = None;
+        Some(reason)
+    }
+
+    // For a StopReason::Breakpoint from the CPU, which only knows addresses:
+    // why the console should open, or None when the breakpoint there is for
+    // another bank and execution should resume. A hit ends any to/finish/frame
+    // run
+    pub fn breakpoint_stop(&mut self, cpu: &CPU) -> Option<String> {
+        let breakpoint = self.breakpoint_hit(cpu)?;
+        self.run_to = None;
+        Some(format!("Breakpoint at {}", breakpoint))
+    }
+
+    // Writes the frozen values back, meant to be called before every instruction
+    pub fn apply_freezes(&self, cpu: &mut CPU) {
+        for (&addr, &value) in &self.freezes {
+            cpu.write_mem(addr, value);
+        }
+    }
+
+    pub fn execute(&mut self, cpu: &mut CPU, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            ["peek", addr] => {
+                let addr = eval_expr(addr)?;
+                format!("{} = ${:02X}", cpu.bus.describe_address(addr), cpu.peek_mem(addr))
+            }
+            ["poke", addr, value] => {
+                let (addr, value) = (eval_expr(addr)?, eval_byte(value)?);
+                check_writable(cpu, addr)?;
+                cpu.write_mem(addr, value);
+                format!("{} <- ${:02X}", cpu.bus.describe_address(addr), value)
+            }
+            ["pokew", addr, value] => {
+                let (addr, value) = (eval_expr(addr)?, eval_expr(value)?);
+                check_writable(cpu, addr)?;
+                check_writable(cpu, addr.wrapping_add(1))?;
+                cpu.write_mem_u16(addr, value);
+                format!("{} <- ${:04X}", cpu.bus.describe_address(addr), value)
+            }
+            ["freeze", addr, value] => {
+                let (addr, value) = (eval_expr(addr)?, eval_byte(value)?);
+                check_writable(cpu, addr)?;
+                self.freezes.insert(addr, value);
+                cpu.write_mem(addr, value);
+                format!("{} frozen at ${:02X}", cpu.bus.describe_address(addr), value)
+            }
+            ["unfreeze", addr] => {
+                let addr = eval_expr(addr)?;
+                self.freezes
+                    .remove(&addr)
+                    .ok_or(format!("${:04X} is not frozen", addr))?;
+                format!("{} unfrozen", cpu.bus.describe_address(addr))
+            }
+            ["freezes"] => self
+                .freezes
+                .iter()
+                .map(|(addr, value)| format!("${:04X} = ${:02X}", addr, value))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ["break", spec] => {
+                let breakpoint = Breakpoint::parse(spec)?;
+                self.breakpoints.insert(breakpoint);
+                cpu.add_breakpoint(breakpoint.address);
+                format!("Breakpoint at {}", breakpoint)
+            }
+            ["delete", spec] => {
+                let breakpoint = Breakpoint::parse(spec)?;
+                if !self.breakpoints.remove(&breakpoint) {
+                    return Err(format!("No breakpoint at {}", breakpoint));
+                }
+                // Other banks may still break at that address
+                if !self.breakpoints.iter().any(|bp| bp.address == breakpoint.address) {
+                    cpu.remove_breakpoint(breakpoint.address);
+                }
+                format!("Breakpoint at {} deleted", breakpoint)
+            }
+            ["breakpoints"] => self
+                .breakpoints
+                .iter()
+                .map(|bp| bp.to_string())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ["watch", range, kind @ ..] if kind.len() <= 1 => {
+                let watchpoint = parse_watchpoint(range, kind.first().copied())?;
+                cpu.add_watchpoint(watchpoint);
+                format!("Watching {}", watchpoint)
+            }
+            ["unwatch", range, kind @ ..] if kind.len() <= 1 => {
+                let watchpoint = parse_watchpoint(range, kind.first().copied())?;
+                if !cpu.remove_watchpoint(watchpoint) {
+                    return Err(format!("Not watching {}", watchpoint));
+                }
+                format!("Stopped watching {}", watchpoint)
+            }
+            ["watchpoints"] => cpu
+                .watchpoints()
+                .map(|w| w.to_string())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ["to", addr] => {
+                let addr = eval_expr(addr)?;
+                self.run_to = Some(RunTo::Address(addr));
+                format!("Running to ${:04X}", addr)
+            }
+            ["finish"] => {
+                self.run_to = Some(RunTo::Return { stack_pointer: cpu.stack_pointer });
+                "Running until return".to_string()
+            }
+            ["frame"] => {
+                let handler = u16::from_le_bytes([cpu.peek_mem(0xFFFA), cpu.peek_mem(0xFFFB)]);
+                self.run_to = Some(RunTo::Frame { handler });
+                format!("Running to the NMI handler at ${:04X}", handler)
+            }
+            ["back", count @ ..] if count.len() <= 1 => {
+                let count = count.first().map_or(Ok(1), |count| eval_expr(count))?;
+                let undone = (0..count).take_while(|_| cpu.step_back()).count();
+                if undone == 0 && cpu.undo_depth() == 0 {
+                    return Err("Undo is off, turn it on with 'undo <depth>'".to_string());
+                }
+                if undone == 0 {
+                    return Err("Nothing to step back over".to_string());
+                }
+                let plural = if undone == 1 { "" } else { "s" };
+                format!("Stepped back {} instruction{} to ${:04X}", undone, plural, cpu.program_counter)
+            }
+            ["undo", depth] => {
+                let depth = eval_expr(depth)?;
+                cpu.set_undo_depth(depth as usize);
+                match depth {
+                    0 => "Undo off".to_string(),
+                    _ => format!("'back' can undo up to {} instructions", depth),
+                }
+            }
+            ["history"] => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, command)| format!("{:>3}  {}", i + 1, command))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ["calls"] if cpu.call_stack().is_empty() => "No calls".to_string(),
+            ["calls"] => cpu
+                .call_stack()
+                .iter()
+                .rev()
+                .map(|frame| frame.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ["io"] => cpu
+                .bus
+                .peek_io_registers()
+                .iter()
+                .map(|&(addr, value)| format!("{} = ${:02X}", cpu.bus.describe_address(addr), value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ["stats"] => {
+                let stats = cpu.bus.stats();
+                REGIONS
+                    .iter()
+                    .map(|&region| {
+                        format!(
+                            "{:<14} {:>10} reads {:>10} writes",
+                            format!("{:?}", region),
+                            stats.reads(region),
+                            stats.writes(region)
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            ["resetstats"] => {
+                cpu.bus.reset_stats();
+                "Memory traffic counters cleared".to_string()
+            }
+            ["help"] => HELP.to_string(),
+            _ => return Err(format!("Unknown command: {}\n{}", line, HELP)),
+        };
+        self.history.push(line.to_string());
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::StopReason;
+    use crate::irq::IrqSource;
+    use crate::rom::ROM;
+
+    #[test]
+    fn test_eval_expr() {
+        assert_eq!(eval_expr("$07DD"), Ok(0x07DD));
+        assert_eq!(eval_expr("0x63"), Ok(0x63));
+        assert_eq!(eval_expr("%1010"), Ok(10));
+        assert_eq!(eval_expr("$0200 + 32 - 1"), Ok(0x021F));
+        assert!(eval_expr("-1").is_err());
+        assert!(eval_expr("$0200+").is_err());
+        assert!(eval_expr("lives").is_err());
+        assert!(eval_expr("").is_err());
+    }
+
+    #[test]
+    fn test_poke_and_freeze() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+
+        assert_eq!(debugger.execute(&mut cpu, "poke $07DD 0x63"), Ok("$07DD RAM $07DD <- $63".to_string()));
+        assert_eq!(cpu.read_mem(0x07DD), 0x63);
+        debugger.execute(&mut cpu, "pokew $10 $1234").unwrap();
+        assert_eq!(cpu.read_mem_u16(0x10), 0x1234);
+        assert!(debugger.execute(&mut cpu, "poke $10 $100").is_err());
+        // 'nes run' attaches no PPU
+        assert_eq!(
+            debugger.execute(&mut cpu, "poke $2000 $80"),
+            Err("$2000 PPUCTRL: no device attached to write to".to_string())
+        );
+        assert!(debugger.execute(&mut cpu, "pokew $1FFF $1234").is_err());
+        assert!(debugger.execute(&mut cpu, "freeze $2001 0").is_err());
+        assert_eq!(cpu.read_mem(0x1FFF), 0);
+
+        debugger.execute(&mut cpu, "freeze $20 9").unwrap();
+        cpu.write_mem(0x20, 0);
+        debugger.apply_freezes(&mut cpu);
+        assert_eq!(cpu.read_mem(0x20), 9);
+        debugger.execute(&mut cpu, "unfreeze $20").unwrap();
+        assert!(debugger.freezes().is_empty());
+
+        // Failed commands are not kept in the history
+        assert_eq!(debugger.history().len(), 4);
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.execute(&mut cpu, "calls"), Ok("No calls".to_string()));
+        // JSR $8003 / JSR $8006
+        cpu.load_program(vec![0x20, 0x03, 0x80, 0x20, 0x06, 0x80, 0xEA]);
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            debugger.execute(&mut cpu, "calls"),
+            Ok("JSR $8006, returns to $8006\nJSR $8003, returns to $8003".to_string())
+        );
+    }
+
+    #[test]
+    fn test_back() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.execute(&mut cpu, "back"),
+            Err("Undo is off, turn it on with 'undo <depth>'".to_string())
+        );
+        // INX / INX / INX, set up like 'nes run --debug' does
+        cpu.load_program(vec![0xE8, 0xE8, 0xE8]);
+        cpu.reset();
+        debugger.attach(&mut cpu);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(debugger.execute(&mut cpu, "back"), Ok("Stepped back 1 instruction to $8002".to_string()));
+        assert_eq!(debugger.execute(&mut cpu, "back 5"), Ok("Stepped back 2 instructions to $8000".to_string()));
+        assert_eq!(cpu.index_register_x, 0);
+        assert_eq!(debugger.execute(&mut cpu, "back"), Err("Nothing to step back over".to_string()));
+    }
+
+    #[test]
+    fn test_undo_depth() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        // INX / INX / INX
+        cpu.load_program(vec![0xE8, 0xE8, 0xE8]);
+        cpu.reset();
+        assert_eq!(debugger.execute(&mut cpu, "undo 2"), Ok("'back' can undo up to 2 instructions".to_string()));
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(debugger.execute(&mut cpu, "back 3"), Ok("Stepped back 2 instructions to $8001".to_string()));
+        assert_eq!(debugger.execute(&mut cpu, "undo 0"), Ok("Undo off".to_string()));
+        assert_eq!(cpu.undo_depth(), 0);
+    }
+
+    #[test]
+    fn test_io_registers() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        cpu.bus.assert_irq(IrqSource::ApuFrame);
+        assert_eq!(debugger.execute(&mut cpu, "io"), Ok("$4015 SND_CHN = $40".to_string()));
+        assert!(cpu.bus.irq_pending());
+    }
+
+    #[test]
+    fn test_bank_breakpoints() {
+        let mut rom = ROM::empty();
+        // 32KB PRG: bank 0 at $8000, bank 1 at $C000
+        rom.prg_rom = vec![0; 0x8000];
+        let mut cpu = CPU::new(Bus::new(rom));
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut cpu, "break 1:$C010").unwrap();
+        debugger.execute(&mut cpu, "break 0:$C020").unwrap();
+        debugger.execute(&mut cpu, "break $0600").unwrap();
+        assert_eq!(debugger.execute(&mut cpu, "breakpoints"), Ok("$0600\n0:$C020\n1:$C010".to_string()));
+
+        cpu.program_counter = 0xC010;
+        assert_eq!(debugger.breakpoint_hit(&cpu), Some(Breakpoint { bank: Some(1), address: 0xC010 }));
+        // Bank 0 is not mapped at $C020
+        cpu.program_counter = 0xC020;
+        assert_eq!(debugger.breakpoint_hit(&cpu), None);
+        cpu.program_counter = 0x0600;
+        assert!(debugger.breakpoint_hit(&cpu).is_some());
+
+        debugger.execute(&mut cpu, "delete $0600").unwrap();
+        assert!(debugger.execute(&mut cpu, "delete $0600").is_err());
+        assert_eq!(debugger.breakpoints().len(), 2);
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        cpu.program_counter = 0x0600;
+        assert_eq!(debugger.stop_reason(&cpu), None);
+
+        debugger.execute(&mut cpu, "to $0610").unwrap();
+        assert!(debugger.is_running_to());
+        assert_eq!(debugger.stop_reason(&cpu), None);
+        cpu.program_counter = 0x0610;
+        assert_eq!(debugger.stop_reason(&cpu), Some("Reached $0610".to_string()));
+        assert!(!debugger.is_running_to());
+
+        // Inside a subroutine, the caller's return address is above SP
+        cpu.stack_pointer = 0xFB;
+        debugger.execute(&mut cpu, "finish").unwrap();
+        cpu.stack_pointer = 0xF9;
+        assert_eq!(debugger.stop_reason(&cpu), None);
+        cpu.stack_pointer = 0xFD;
+        cpu.program_counter = 0x0603;
+        assert_eq!(debugger.stop_reason(&cpu), Some("Returned to $0603".to_string()));
+
+        cpu.write_mem_u16(0xFFFA, 0x0700);
+        debugger.execute(&mut cpu, "frame").unwrap();
+        // A breakpoint on the way ends the run
+        debugger.execute(&mut cpu, "break $0650").unwrap();
+        cpu.program_counter = 0x0650;
+        assert_eq!(debugger.breakpoint_stop(&cpu), Some("Breakpoint at $0650".to_string()));
+        assert!(!debugger.is_running_to());
+    }
+
+    #[test]
+    fn test_breakpoints_stop_execute() {
+        let mut rom = ROM::empty();
+        // 32KB PRG of INX: bank 0 at $8000, bank 1 at $C000
+        rom.prg_rom = vec![0xE8; 0x8000];
+        rom.prg_rom[0x7FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.reset();
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut cpu, "break 0:$8003").unwrap();
+        debugger.execute(&mut cpu, "break 1:$8005").unwrap();
+        debugger.execute(&mut cpu, "break $8006").unwrap();
+        assert_eq!(cpu.breakpoints().collect::<Vec<_>>(), vec![0x8003, 0x8005, 0x8006]);
+
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8003)));
+        assert_eq!(debugger.breakpoint_stop(&cpu), Some("Breakpoint at 0:$8003".to_string()));
+        // Bank 1 is not mapped at $8005, the run goes on
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8005)));
+        assert_eq!(debugger.breakpoint_stop(&cpu), None);
+        assert_eq!(cpu.execute(), Ok(StopReason::Breakpoint(0x8006)));
+
+        debugger.execute(&mut cpu, "delete 1:$8005").unwrap();
+        debugger.execute(&mut cpu, "delete $8006").unwrap();
+        assert_eq!(cpu.breakpoints().collect::<Vec<_>>(), vec![0x8003]);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+
+        assert_eq!(debugger.execute(&mut cpu, "watch $0200..$02FF w"), Ok("Watching $0200..$02FF write".to_string()));
+        debugger.execute(&mut cpu, "watch $2002 r").unwrap();
+        debugger.execute(&mut cpu, "watch $10").unwrap();
+        assert_eq!(
+            debugger.execute(&mut cpu, "watchpoints"),
+            Ok("$0010 read/write\n$0200..$02FF write\n$2002 read".to_string())
+        );
+        assert!(debugger.execute(&mut cpu, "watch $0300..$0200").is_err());
+        assert!(debugger.execute(&mut cpu, "watch $0300 x").is_err());
+
+        debugger.execute(&mut cpu, "unwatch $10").unwrap();
+        assert!(debugger.execute(&mut cpu, "unwatch $10").is_err());
+        assert_eq!(cpu.watchpoints().count(), 2);
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut debugger = Debugger::new();
+        for command in ["break 1:$C010", "break $0600", "watch $2002 r", "freeze $20 9"] {
+            debugger.execute(&mut cpu, command).unwrap();
+        }
+        let saved = debugger.save(&cpu);
+        assert_eq!(saved, "break $0600\nbreak 1:$C010\nwatch $2002..$2002 r\nfreeze $0020 $09");
+
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        let mut restored = Debugger::new();
+        restored.restore(&mut cpu, &saved).unwrap();
+        assert_eq!(restored.breakpoints(), debugger.breakpoints());
+        assert_eq!(restored.freezes(), debugger.freezes());
+        assert_eq!(restored.save(&cpu), saved);
+        assert!(restored.history().is_empty());
+        assert!(restored.restore(&mut cpu, "break nowhere").is_err());
+
+        // A freeze on a missing device doesn't stop the rest from loading
+        let mut restored = Debugger::new();
+        assert_eq!(
+            restored.restore(&mut cpu, "freeze $2000 $80\nfreeze $0020 $09"),
+            Err("freeze $2000 $80: $2000 PPUCTRL: no device attached to write to".to_string())
+        );
+        assert_eq!(restored.freezes().len(), 1);
+    }
+}
@@ -0,0 +1,200 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::cpu::AddressingMode;
+use crate::opcodes::{OpCode, CPU_OPCODE_TABLE};
+
+// FCEUX code/data logger flags, one byte per PRG byte
+const CDL_CODE: u8 = 0b0000_0001;
+const CDL_DATA: u8 = 0b0000_0010;
+
+fn format_address(addr: u16, labels: &BTreeMap<u16, String>) -> String {
+    match labels.get(&addr) {
+        Some(label) => label.clone(),
+        None => format!("${:04X}", addr),
+    }
+}
+
+pub(crate) fn format_operand(opcode: &OpCode, addr: u16, args: &[u8], labels: &BTreeMap<u16, String>) -> String {
+    let zero_page = || format!("${:02X}", args[0]);
+    let absolute = || format_address(u16::from_le_bytes([args[0], args[1]]), labels);
+    match opcode.addressing_mode {
+        AddressingMode::Immediate => format!("#${:02X}", args[0]),
+        AddressingMode::ZeroPage => zero_page(),
+        AddressingMode::ZeroPage_X => format!("{},X", zero_page()),
+        AddressingMode::ZeroPage_Y => format!("{},Y", zero_page()),
+        AddressingMode::Absolute => absolute(),
+        AddressingMode::Absolute_X => format!("{},X", absolute()),
+        AddressingMode::Absolute_Y => format!("{},Y", absolute()),
+        AddressingMode::Indirect_X => format!("({},X)", zero_page()),
+        AddressingMode::Indirect_Y => format!("({}),Y", zero_page()),
+        AddressingMode::NoneAddressing => match opcode.bytes {
+            // Relative branch
+            2 => {
+                let target = addr.wrapping_add(2).wrapping_add(args[0] as i8 as u16);
+                format_address(target, labels)
+            }
+            // JMP indirect
+            3 => format!("({})", absolute()),
+            _ => match opcode.label {
+                "ASL" | "LSR" | "ROL" | "ROR" => "A".to_string(),
+                _ => String::new(),
+            },
+        },
+    }
+}
+
+// An instruction decoded by decode(), or a byte it left as data
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub operands: Vec<u8>,
+    // ".db" for data bytes
+    pub mnemonic: &'static str,
+    // None for data bytes
+    pub mode: Option<AddressingMode>,
+}
+
+impl DecodedInstruction {
+    pub fn is_data(&self) -> bool {
+        self.mode.is_none()
+    }
+
+    // Bytes taken by the instruction
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    // "C000  A9 42     LDA #$42", with `labels` in place of the addresses
+    // they name
+    pub fn format(&self, labels: &BTreeMap<u16, String>) -> String {
+        let operand = match CPU_OPCODE_TABLE[self.opcode as usize].filter(|_| !self.is_data()) {
+            Some(opcode) => format_operand(opcode, self.address, &self.operands, labels),
+            None => format!("${:02X}", self.opcode),
+        };
+        let hex: Vec<String> = core::iter::once(&self.opcode)
+            .chain(&self.operands)
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        format!("{:04X}  {:<8}  {} {}", self.address, hex.join(" "), self.mnemonic, operand)
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(&BTreeMap::new()))
+    }
+}
+
+// Decodes `program` as if mapped at `base`. Bytes marked as data-only in the
+// CDL, unknown opcodes and truncated instructions come back as data bytes
+pub fn decode(program: &[u8], base: u16, cdl: Option<&[u8]>) -> Vec<DecodedInstruction> {
+    let mut instructions = vec![];
+    let mut pos: usize = 0;
+    while pos < program.len() {
+        let address = base.wrapping_add(pos as u16);
+        let is_data = cdl
+            .and_then(|cdl| cdl.get(pos))
+            .is_some_and(|flags| flags & CDL_DATA != 0 && flags & CDL_CODE == 0);
+        let opcode = CPU_OPCODE_TABLE[program[pos] as usize]
+            .filter(|opcode| !is_data && pos + opcode.bytes as usize <= program.len());
+
+        let instruction = match opcode {
+            Some(opcode) => DecodedInstruction {
+                address,
+                opcode: opcode.opcode,
+                operands: program[pos + 1..pos + opcode.bytes as usize].to_vec(),
+                mnemonic: opcode.label,
+                mode: Some(opcode.addressing_mode),
+            },
+            None => DecodedInstruction {
+                address,
+                opcode: program[pos],
+                operands: vec![],
+                mnemonic: ".db",
+                mode: None,
+            },
+        };
+        pos += instruction.size();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// decode() as text, one line per instruction, each label on its own line
+pub fn disassemble(
+    program: &[u8],
+    base: u16,
+    labels: &BTreeMap<u16, String>,
+    cdl: Option<&[u8]>,
+) -> Vec<String> {
+    let mut lines = vec![];
+    for instruction in decode(program, base, cdl) {
+        if let Some(label) = labels.get(&instruction.address) {
+            lines.push(format!("{}:", label));
+        }
+        lines.push(instruction.format(labels));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let mut labels = BTreeMap::new();
+        labels.insert(0xC000, "reset".to_string());
+        let program = [0xA9, 0x42, 0x8D, 0x00, 0x02, 0xD0, 0xF9, 0x6C, 0x00, 0xC0, 0x0A, 0x03];
+        let lines = disassemble(&program, 0xC000, &labels, None);
+        assert_eq!(
+            lines,
+            vec![
+                "reset:",
+                "C000  A9 42     LDA #$42",
+                "C002  8D 00 02  STA $0200",
+                "C005  D0 F9     BNE reset",
+                "C007  6C 00 C0  JMP (reset)",
+                "C00A  0A        ASL A",
+                "C00B  03        .db $03",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_with_cdl_data() {
+        let program = [0xA9, 0x42, 0xA9, 0x10];
+        let cdl = [CDL_CODE, CDL_CODE, CDL_DATA, CDL_DATA];
+        let lines = disassemble(&program, 0x8000, &BTreeMap::new(), Some(&cdl));
+        assert_eq!(lines[1], "8002  A9        .db $A9");
+        assert_eq!(lines[2], "8003  10        .db $10");
+    }
+
+    #[test]
+    fn test_decode() {
+        let program = [0xB1, 0x10, 0x4C, 0x00, 0x80, 0x03];
+        let instructions = decode(&program, 0x8000, None);
+        assert_eq!(
+            instructions[0],
+            DecodedInstruction {
+                address: 0x8000,
+                opcode: 0xB1,
+                operands: vec![0x10],
+                mnemonic: "LDA",
+                mode: Some(AddressingMode::Indirect_Y),
+            }
+        );
+        assert_eq!(instructions[1].address, 0x8002);
+        assert_eq!(instructions[1].operands, vec![0x00, 0x80]);
+        assert!(instructions[2].is_data());
+        assert_eq!(instructions[2].to_string(), "8005  03        .db $03");
+    }
+}
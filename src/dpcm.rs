@@ -0,0 +1,149 @@
+use alloc::vec::Vec;
+
+use crate::cpu::Mem;
+
+const DMC_ADDRESS: u16 = 0x4012;
+const DMC_LENGTH: u16 = 0x4013;
+const CPU_CLOCK_NTSC: u32 = 1_789_773;
+// How far apart the $4012 and $4013 stores can be when scanning
+const SCAN_DISTANCE: usize = 16;
+
+// Output periods of the DMC in CPU cycles (NTSC), indexed by $4010 bits 0-3
+pub const RATE_PERIODS: [u32; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleRef {
+    pub address: u16,
+    pub length: usize,
+}
+
+impl SampleRef {
+    // Decodes the values games write to $4012 and $4013
+    pub fn from_registers(address: u8, length: u8) -> Self {
+        Self {
+            address: 0xC000 + address as u16 * 64,
+            length: length as usize * 16 + 1,
+        }
+    }
+}
+
+pub fn sample_rate(rate_index: u8) -> u32 {
+    CPU_CLOCK_NTSC / RATE_PERIODS[(rate_index & 0x0F) as usize]
+}
+
+// Reads sample bytes the way the DMC does, wrapping from $FFFF to $8000
+pub fn read_sample<M: Mem>(mem: &M, sample: SampleRef) -> Vec<u8> {
+    let mut addr = sample.address;
+    (0..sample.length)
+        .map(|_| {
+            let byte = mem.peek_mem(addr);
+            addr = if addr == 0xFFFF { 0x8000 } else { addr + 1 };
+            byte
+        })
+        .collect()
+}
+
+// Expands 1-bit deltas (LSB first) into 7-bit output levels: a 1 bit adds 2,
+// a 0 bit subtracts 2, and the level never leaves 0..=127
+pub fn decode(bytes: &[u8], initial_level: u8) -> Vec<u8> {
+    let mut level = initial_level.min(127);
+    let mut levels = Vec::with_capacity(bytes.len() * 8);
+    for byte in bytes {
+        for bit in 0..8 {
+            if byte & (1 << bit) != 0 {
+                if level <= 125 {
+                    level += 2;
+                }
+            } else if level >= 2 {
+                level -= 2;
+            }
+            levels.push(level);
+        }
+    }
+    levels
+}
+
+// Looks for "LDA #imm / STA $4012" and "LDA #imm / STA $4013" pairs close to
+// each other in PRG code, the usual way games start a sample
+pub fn scan_sample_writes(prg: &[u8]) -> Vec<SampleRef> {
+    let store_at = |i: usize, register: u16| {
+        let w = prg.get(i..i + 5)?;
+        let stores = w[0] == 0xA9 && w[2] == 0x8D && u16::from_le_bytes([w[3], w[4]]) == register;
+        stores.then_some(w[1])
+    };
+    let mut samples = Vec::new();
+    for at in 0..prg.len() {
+        let Some(address) = store_at(at, DMC_ADDRESS) else {
+            continue;
+        };
+        let nearby = at.saturating_sub(SCAN_DISTANCE)..=at + SCAN_DISTANCE;
+        if let Some(length) = nearby.filter_map(|i| store_at(i, DMC_LENGTH)).next() {
+            let sample = SampleRef::from_registers(address, length);
+            if !samples.contains(&sample) {
+                samples.push(sample);
+            }
+        }
+    }
+    samples
+}
+
+// 8-bit mono PCM WAV of the decoded levels
+pub fn to_wav(levels: &[u8], sample_rate: u32) -> Vec<u8> {
+    let data_len = levels.len() as u32;
+    let mut wav = Vec::with_capacity(44 + levels.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes()); // byte rate
+    wav.extend_from_slice(&1u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&8u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend(levels.iter().map(|level| level * 2));
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::ROM;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&[0b0000_0111], 64), vec![66, 68, 70, 68, 66, 64, 62, 60]);
+        // Levels saturate instead of wrapping
+        assert_eq!(decode(&[0xFF], 125), vec![127; 8]);
+        assert_eq!(decode(&[0x00], 1), vec![1; 8]);
+    }
+
+    #[test]
+    fn test_scan_and_read_sample() {
+        let mut rom = ROM::empty();
+        // LDA #$04 / STA $4012 / LDA #$01 / STA $4013
+        rom.prg_rom[0..10].copy_from_slice(&[0xA9, 0x04, 0x8D, 0x12, 0x40, 0xA9, 0x01, 0x8D, 0x13, 0x40]);
+        rom.prg_rom[0x4100] = 0xAA;
+        let samples = scan_sample_writes(&rom.prg_rom);
+        assert_eq!(samples, vec![SampleRef { address: 0xC100, length: 17 }]);
+
+        let bus = Bus::new(rom);
+        let bytes = read_sample(&bus, samples[0]);
+        assert_eq!(bytes.len(), 17);
+        assert_eq!(bytes[0], 0xAA);
+    }
+
+    #[test]
+    fn test_to_wav() {
+        let wav = to_wav(&[0, 64, 127], 33143);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav.len(), 47);
+        assert_eq!(&wav[44..], &[0, 128, 254]);
+        assert_eq!(sample_rate(15), 33143);
+    }
+}
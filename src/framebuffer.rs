@@ -0,0 +1,118 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::cpu::Mem;
+
+pub type Palette = [(u8, u8, u8); 16];
+
+// Colors used by the snake game; indexes come from the low nibble of each pixel byte
+pub const DEFAULT_PALETTE: Palette = [
+    (0x00, 0x00, 0x00), // black
+    (0xFF, 0xFF, 0xFF), // white
+    (0x80, 0x80, 0x80), // grey
+    (0xFF, 0x00, 0x00), // red
+    (0x00, 0xFF, 0x00), // green
+    (0x00, 0x00, 0xFF), // blue
+    (0xFF, 0x00, 0xFF), // magenta
+    (0xFF, 0xFF, 0x00), // yellow
+    (0x00, 0xFF, 0xFF), // cyan
+    (0x80, 0x80, 0x80), // grey
+    (0xFF, 0x00, 0x00), // red
+    (0x00, 0xFF, 0x00), // green
+    (0x00, 0x00, 0xFF), // blue
+    (0xFF, 0x00, 0xFF), // magenta
+    (0xFF, 0xFF, 0x00), // yellow
+    (0x00, 0xFF, 0xFF), // cyan
+];
+
+// Easy6502-style video memory: one byte per pixel, row by row, starting at `base`.
+pub struct SimpleFramebufferDevice {
+    base: u16,
+    width: usize,
+    height: usize,
+    palette: Palette,
+    pixels: Vec<u8>,
+    dirty: bool,
+}
+
+impl SimpleFramebufferDevice {
+    pub fn new(base: u16, width: usize, height: usize) -> Self {
+        Self {
+            base,
+            width,
+            height,
+            palette: DEFAULT_PALETTE,
+            pixels: vec![0; width * height],
+            dirty: true,
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.dirty = true;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        addr >= self.base && ((addr - self.base) as usize) < self.pixels.len()
+    }
+
+    // Writes the screen as RGB24 into `frame` (width * height * 3 bytes).
+    // Returns false when nothing changed since the last call.
+    pub fn render_rgb(&mut self, frame: &mut [u8]) -> bool {
+        if !self.dirty {
+            return false;
+        }
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            let (r, g, b) = self.palette[(pixel & 0x0F) as usize];
+            frame[i * 3] = r;
+            frame[i * 3 + 1] = g;
+            frame[i * 3 + 2] = b;
+        }
+        self.dirty = false;
+        true
+    }
+}
+
+impl Mem for SimpleFramebufferDevice {
+    fn read_mem(&self, addr: u16) -> u8 {
+        self.pixels[(addr - self.base) as usize]
+    }
+
+    fn write_mem(&mut self, addr: u16, value: u8) {
+        let pixel = &mut self.pixels[(addr - self.base) as usize];
+        if *pixel != value {
+            *pixel = value;
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::ROM;
+
+    #[test]
+    fn test_bus_routes_writes_to_framebuffer() {
+        let mut bus = Bus::new(ROM::empty());
+        bus.attach_framebuffer(SimpleFramebufferDevice::new(0x0200, 2, 2));
+        bus.write_mem(0x0201, 0x01);
+        bus.write_mem(0x0204, 0x42);
+
+        let mut frame = [0u8; 2 * 2 * 3];
+        let framebuffer = bus.framebuffer_mut().unwrap();
+        assert!(framebuffer.render_rgb(&mut frame));
+        assert_eq!(frame[3..6], [0xFF, 0xFF, 0xFF]);
+        assert!(!framebuffer.render_rgb(&mut frame));
+        // Addresses past the device still reach RAM
+        assert_eq!(bus.read_mem(0x0204), 0x42);
+    }
+}
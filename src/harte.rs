@@ -0,0 +1,366 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::cpu::{CpuError, Mem, CPU};
+use crate::opcodes;
+
+// Runner for Tom Harte's ProcessorTests single-step vectors
+// (https://github.com/SingleStepTests/ProcessorTests, nes6502/v1 for the
+// 2A03 without decimal mode): one JSON file per opcode, each case an initial
+// and a final CPU + memory state around a single instruction, and its bus
+// activity cycle by cycle. Only the number of cycles is compared, not what the
+// bus did on each one: the CPU doesn't step cycles yet.
+
+// Just enough JSON for the test files: no escapes, floats or exponents
+#[derive(Debug, PartialEq)]
+enum Json {
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+    Literal,
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.input.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.input.get(self.pos) != Some(&byte) {
+            return Err(format!("Expected '{}' at byte {}", byte as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Parses `item` repeatedly between `open` and `close`, separated by commas
+    fn list<T>(&mut self, open: u8, close: u8, mut item: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        self.expect(open)?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.input.get(self.pos) == Some(&close) {
+            self.pos += 1;
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            self.skip_whitespace();
+            match self.input.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(&b) if b == close => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                _ => return Err(format!("Expected ',' or '{}' at byte {}", close as char, self.pos)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while self.input.get(self.pos).is_some_and(|&b| b != b'"') {
+            self.pos += 1;
+        }
+        let value = String::from_utf8_lossy(&self.input[start..self.pos]).into_owned();
+        self.expect(b'"')?;
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.input.get(self.pos) {
+            Some(b'{') => Ok(Json::Object(self.list(b'{', b'}', |p| {
+                let key = p.string()?;
+                p.expect(b':')?;
+                Ok((key, p.value()?))
+            })?)),
+            Some(b'[') => Ok(Json::Array(self.list(b'[', b']', Self::value)?)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b) if b.is_ascii_alphabetic() => {
+                // true, false, null
+                while self.input.get(self.pos).is_some_and(u8::is_ascii_alphabetic) {
+                    self.pos += 1;
+                }
+                Ok(Json::Literal)
+            }
+            _ => {
+                let start = self.pos;
+                if self.input.get(self.pos) == Some(&b'-') {
+                    self.pos += 1;
+                }
+                while self.input.get(self.pos).is_some_and(u8::is_ascii_digit) {
+                    self.pos += 1;
+                }
+                std::str::from_utf8(&self.input[start..self.pos])
+                    .ok()
+                    .and_then(|digits| digits.parse().ok())
+                    .map(Json::Number)
+                    .ok_or(format!("Invalid value at byte {}", start))
+            }
+        }
+    }
+}
+
+fn parse_json(input: &[u8]) -> Result<Json, String> {
+    Parser { input, pos: 0 }.value()
+}
+
+#[derive(Debug, PartialEq)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn from_json(json: &Json) -> Option<Self> {
+        let byte = |key| json.get(key)?.as_number().map(|n| n as u8);
+        let ram = json
+            .get("ram")?
+            .as_array()?
+            .iter()
+            .map(|pair| {
+                let pair = pair.as_array()?;
+                Some((pair.first()?.as_number()? as u16, pair.get(1)?.as_number()? as u8))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            pc: json.get("pc")?.as_number()? as u16,
+            s: byte("s")?,
+            a: byte("a")?,
+            x: byte("x")?,
+            y: byte("y")?,
+            p: byte("p")?,
+            ram,
+        })
+    }
+}
+
+struct Case {
+    name: String,
+    initial: State,
+    expected: State,
+    cycles: usize,
+}
+
+fn parse_cases(raw: &[u8]) -> Result<Vec<Case>, String> {
+    let json = parse_json(raw)?;
+    json.as_array()
+        .ok_or("Expected an array of test cases")?
+        .iter()
+        .map(|case| {
+            let name = match case.get("name") {
+                Some(Json::String(name)) => name.clone(),
+                _ => String::new(),
+            };
+            let state = |key| case.get(key).and_then(State::from_json);
+            let cycles = case.get("cycles").and_then(Json::as_array).map(<[Json]>::len);
+            match (state("initial"), state("final"), cycles) {
+                (Some(initial), Some(expected), Some(cycles)) => Ok(Case { name, initial, expected, cycles }),
+                _ => Err(format!("Malformed test case {}", name)),
+            }
+        })
+        .collect()
+}
+
+// Runs one case on `cpu`, which is reused between cases: only the registers
+// and the memory listed in the case are set up. The reset clears a jam left
+// by a previous KIL case
+fn run_case(cpu: &mut CPU, case: &Case) -> Result<(), String> {
+    cpu.reset();
+    let initial = &case.initial;
+    for &(addr, value) in &initial.ram {
+        cpu.write_mem(addr, value);
+    }
+    cpu.program_counter = initial.pc;
+    cpu.stack_pointer = initial.s;
+    cpu.register_accumulator = initial.a;
+    cpu.index_register_x = initial.x;
+    cpu.index_register_y = initial.y;
+    cpu.status.status = initial.p;
+
+    // A jammed CPU has no instruction cycles to compare
+    let step = panic::catch_unwind(AssertUnwindSafe(|| cpu.step())).map_err(|_| format!("{}: panicked", case.name))?;
+    let cycles = match step {
+        Ok(cycles) => Some(cycles as usize),
+        Err(CpuError::Jammed { .. }) => None,
+        Err(e) => return Err(format!("{}: {}", case.name, e)),
+    };
+
+    let actual = State {
+        pc: cpu.program_counter,
+        s: cpu.stack_pointer,
+        a: cpu.register_accumulator,
+        x: cpu.index_register_x,
+        y: cpu.index_register_y,
+        p: cpu.status.status,
+        ram: case.expected.ram.iter().map(|&(addr, _)| (addr, cpu.peek_mem(addr))).collect(),
+    };
+    if actual != case.expected {
+        return Err(format!("{}: expected {:02X?}, got {:02X?}", case.name, case.expected, actual));
+    }
+    if let Some(cycles) = cycles.filter(|&cycles| cycles != case.cycles) {
+        return Err(format!("{}: expected {} cycles, got {}", case.name, case.cycles, cycles));
+    }
+    Ok(())
+}
+
+pub struct OpcodeResult {
+    pub opcode: u8,
+    pub passed: usize,
+    pub total: usize,
+    pub first_failure: Option<String>,
+}
+
+// Runs every case of one opcode file
+pub fn run_file(opcode: u8, raw: &[u8]) -> Result<OpcodeResult, String> {
+    let cases = parse_cases(raw)?;
+    let mut cpu = CPU::new(Bus::flat());
+    cpu.set_trace(false);
+    cpu.set_halt_on_brk(false);
+    let mut result = OpcodeResult { opcode, passed: 0, total: cases.len(), first_failure: None };
+    for case in &cases {
+        match run_case(&mut cpu, case) {
+            Ok(()) => result.passed += 1,
+            Err(e) => {
+                result.first_failure.get_or_insert(e);
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub struct Report {
+    pub results: Vec<OpcodeResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed == r.total)
+    }
+
+    // One line per opcode with its pass rate, then the first failure of each
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        for r in &self.results {
+            let label = opcodes::CPU_OPCODES_MAP.get(&r.opcode).map_or("???", |op| op.label);
+            let rate = if r.total == 0 { 0.0 } else { r.passed as f64 * 100.0 / r.total as f64 };
+            let _ = writeln!(out, "{:02X} {:<4} {:>5}/{:<5} {:>5.1}%", r.opcode, label, r.passed, r.total, rate);
+        }
+        for failure in self.results.iter().filter_map(|r| r.first_failure.as_ref()) {
+            let _ = writeln!(out, "{}", failure);
+        }
+        out.trim_end().to_string()
+    }
+}
+
+// Runs every "xx.json" file in `dir`, in opcode order
+pub fn run_dir(dir: &Path) -> Result<Report, String> {
+    let mut files: Vec<(u8, std::path::PathBuf)> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .flatten()
+        .filter_map(|item| {
+            let path = item.path();
+            let opcode = u8::from_str_radix(path.file_stem()?.to_str()?, 16).ok()?;
+            (path.extension()? == "json").then_some((opcode, path))
+        })
+        .collect();
+    files.sort();
+    let mut results = vec![];
+    for (opcode, path) in files {
+        let raw = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        results.push(run_file(opcode, &raw).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    Ok(Report { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three cases in the ProcessorTests format, the last two deliberately wrong
+    const LDA_IMMEDIATE: &str = r#"[
+        {"name": "a9 42", "initial": {"pc": 1000, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[1000, 169], [1001, 66]]},
+         "final": {"pc": 1002, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36,
+            "ram": [[1000, 169], [1001, 66]]},
+         "cycles": [[1000, 169, "read"], [1001, 66, "read"]]},
+        {"name": "a9 00", "initial": {"pc": 2000, "s": 0, "a": 1, "x": 0, "y": 0, "p": 36,
+            "ram": [[2000, 169], [2001, 0]]},
+         "final": {"pc": 2002, "s": 0, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[2000, 169], [2001, 0]]},
+         "cycles": [[2000, 169, "read"], [2001, 0, "read"]]},
+        {"name": "a9 01", "initial": {"pc": 3000, "s": 0, "a": 0, "x": 0, "y": 0, "p": 36,
+            "ram": [[3000, 169], [3001, 1]]},
+         "final": {"pc": 3002, "s": 0, "a": 1, "x": 0, "y": 0, "p": 36,
+            "ram": [[3000, 169], [3001, 1]]},
+         "cycles": [[3000, 169, "read"], [3001, 1, "read"], [3002, 0, "read"]]}
+    ]"#;
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse_json(br#" {"a": [1, -2], "b": "c", "d": null} "#),
+            Ok(Json::Object(vec![
+                ("a".to_string(), Json::Array(vec![Json::Number(1), Json::Number(-2)])),
+                ("b".to_string(), Json::String("c".to_string())),
+                ("d".to_string(), Json::Literal),
+            ]))
+        );
+        assert!(parse_json(b"[1, 2").is_err());
+    }
+
+    #[test]
+    fn test_run_file() {
+        let result = run_file(0xA9, LDA_IMMEDIATE.as_bytes()).unwrap();
+        assert_eq!((result.passed, result.total), (1, 3));
+        // The final state of the second case is missing Z, so it fails
+        assert!(result.first_failure.unwrap().starts_with("a9 00"));
+
+        // The third one lists a cycle too many
+        let cases = parse_cases(LDA_IMMEDIATE.as_bytes()).unwrap();
+        let mut cpu = CPU::new(Bus::flat());
+        cpu.set_trace(false);
+        assert_eq!(run_case(&mut cpu, &cases[2]), Err("a9 01: expected 3 cycles, got 2".to_string()));
+    }
+}
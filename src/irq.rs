@@ -0,0 +1,77 @@
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+    Mapper,
+    ApuFrame,
+    Dmc,
+}
+
+const IRQ_SOURCES: [IrqSource; 3] = [IrqSource::Mapper, IrqSource::ApuFrame, IrqSource::Dmc];
+
+// The CPU IRQ line is wired-OR: it stays asserted as long as at least one
+// device is pulling it, so every source is tracked independently.
+pub struct IrqLine {
+    sources: u8,
+}
+
+impl IrqLine {
+    pub fn new() -> Self {
+        Self { sources: 0 }
+    }
+
+    fn get_mask(&self, source: IrqSource) -> u8 {
+        match source {
+            IrqSource::Mapper => 0b0000_0001,
+            IrqSource::ApuFrame => 0b0000_0010,
+            IrqSource::Dmc => 0b0000_0100,
+        }
+    }
+
+    pub fn assert(&mut self, source: IrqSource) {
+        self.sources |= self.get_mask(source);
+    }
+
+    pub fn acknowledge(&mut self, source: IrqSource) {
+        self.sources &= !self.get_mask(source);
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.sources != 0
+    }
+
+    pub fn is_asserted_by(&self, source: IrqSource) -> bool {
+        self.sources & self.get_mask(source) != 0
+    }
+
+    pub fn asserting_sources(&self) -> Vec<IrqSource> {
+        IRQ_SOURCES
+            .iter()
+            .filter(|source| self.is_asserted_by(**source))
+            .copied()
+            .collect()
+    }
+}
+
+impl Default for IrqLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acknowledge_keeps_other_sources() {
+        let mut irq = IrqLine::new();
+        irq.assert(IrqSource::Mapper);
+        irq.assert(IrqSource::Dmc);
+        irq.acknowledge(IrqSource::Mapper);
+        assert!(irq.is_asserted());
+        assert_eq!(irq.asserting_sources(), vec![IrqSource::Dmc]);
+        irq.acknowledge(IrqSource::Dmc);
+        assert!(!irq.is_asserted());
+    }
+}
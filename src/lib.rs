@@ -1,8 +1,46 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 #[macro_use]
 extern crate lazy_static;
 
+// Console output is only available with std, embedded frontends get nothing
+#[cfg(feature = "std")]
+macro_rules! log {
+    ($($arg:tt)*) => { std::println!($($arg)*) };
+}
+#[cfg(not(feature = "std"))]
+macro_rules! log {
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+pub mod apu;
 pub mod bus;
+pub mod control;
 pub mod cpu;
+#[cfg(feature = "std")]
+pub mod crash;
+pub mod debugger;
+pub mod disasm;
+pub mod dpcm;
+pub mod framebuffer;
+#[cfg(feature = "harte")]
+pub mod harte;
+pub mod irq;
+#[cfg(feature = "std")]
+pub mod library;
+#[doc(hidden)]
+pub mod messages;
 pub mod opcodes;
+pub mod ppu;
+pub mod prelude;
+pub mod profiler;
 pub mod rom;
+#[doc(hidden)]
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod selftest;
+#[cfg(feature = "std")]
+pub mod session;
+pub mod trace;
 mod status_flags;
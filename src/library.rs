@@ -0,0 +1,172 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::rom::{RomError, ROM};
+
+const INES_HEADER_SIZE: usize = 16;
+
+// CRC-32 (IEEE), as used by No-Intro and most ROM databases
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Compatibility {
+    Supported,
+    UnsupportedMapper,
+    Invalid(String),
+}
+
+#[derive(Debug)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub title: String,
+    // CRC-32 of everything after the iNES header
+    pub crc32: u32,
+    pub mapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub compatibility: Compatibility,
+}
+
+impl LibraryEntry {
+    // None when the file is not an iNES ROM
+    pub fn from_raw(path: &Path, raw: Vec<u8>) -> Option<Self> {
+        if !ROM::is_ines(&raw) {
+            return None;
+        }
+        let title = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let crc32 = crc32(&raw[INES_HEADER_SIZE..]);
+        let mapper = ROM::header_mapper(&raw);
+        let (prg_rom_size, chr_rom_size) = (raw[4] as usize * 0x4000, raw[5] as usize * 0x2000);
+        let compatibility = match ROM::new(raw) {
+            Ok(_) => Compatibility::Supported,
+            Err(RomError::UnsupportedMapper(_)) => Compatibility::UnsupportedMapper,
+            Err(e) => Compatibility::Invalid(e.to_string()),
+        };
+        Some(Self {
+            path: path.to_path_buf(),
+            title,
+            crc32,
+            mapper,
+            prg_rom_size,
+            chr_rom_size,
+            compatibility,
+        })
+    }
+}
+
+// Recursively collects every iNES ROM under `dir`, sorted by path. Files that
+// can't be read are skipped
+pub fn scan(dir: &Path) -> Result<Vec<LibraryEntry>, String> {
+    let mut entries = vec![];
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let read_dir = fs::read_dir(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for item in read_dir.flatten() {
+            let path = item.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Some(entry) = fs::read(&path).ok().and_then(|raw| LibraryEntry::from_raw(&path, raw)) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+pub fn to_json(entries: &[LibraryEntry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let compatibility = match &entry.compatibility {
+                Compatibility::Supported => "supported".to_string(),
+                Compatibility::UnsupportedMapper => "unsupported mapper".to_string(),
+                Compatibility::Invalid(reason) => format!("invalid: {}", reason),
+            };
+            format!(
+                "  {{\"path\": {}, \"title\": {}, \"crc32\": \"{:08X}\", \"mapper\": {}, \"prg_rom_size\": {}, \"chr_rom_size\": {}, \"compatibility\": {}}}",
+                json_string(&entry.path.to_string_lossy()),
+                json_string(&entry.title),
+                entry.crc32,
+                entry.mapper,
+                entry.prg_rom_size,
+                entry.chr_rom_size,
+                json_string(&compatibility)
+            )
+        })
+        .collect();
+    format!("[\n{}\n]", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_entry_from_raw() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x10, 0x00];
+        raw.resize(16 + 0x4000, 0);
+        let entry = LibraryEntry::from_raw(Path::new("roms/Some \"Game\".nes"), raw).unwrap();
+        assert_eq!(entry.title, "Some \"Game\"");
+        assert_eq!(entry.mapper, 1);
+        assert_eq!(entry.prg_rom_size, 0x4000);
+        assert_eq!(entry.compatibility, Compatibility::UnsupportedMapper);
+        assert!(to_json(&[entry]).contains(r#""title": "Some \"Game\"", "#));
+
+        assert!(LibraryEntry::from_raw(Path::new("notes.txt"), b"hello".to_vec()).is_none());
+    }
+
+    #[test]
+    fn test_scan_truncated_rom() {
+        let dir = std::env::temp_dir().join(format!("nes-scan-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // The header promises 32KB of PRG, the file stops after 3 bytes
+        let mut truncated = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x00];
+        truncated.resize(19, 0);
+        fs::write(dir.join("a-truncated.nes"), truncated).unwrap();
+        let mut good = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        good.resize(16 + 0x4000, 0);
+        fs::write(dir.join("b-good.nes"), good).unwrap();
+
+        let entries = scan(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let entries = entries.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].compatibility, Compatibility::Invalid("Invalid NES file".to_string()));
+        assert_eq!(entries[1].compatibility, Compatibility::Supported);
+    }
+}
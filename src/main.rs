@@ -1,106 +1,522 @@
-extern crate sdl2;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::OnceLock;
 
-use nes_emulator::cpu::CPU;
-
-use nes_emulator::cpu::Mem;
 use nes_emulator::bus::Bus;
+use nes_emulator::cpu::{Mem, PowerOnConfig, StopReason, CPU};
+use nes_emulator::crash::write_crash_dump;
+use nes_emulator::debugger::Debugger;
+use nes_emulator::disasm::disassemble;
+use nes_emulator::dpcm::{self, SampleRef};
+#[cfg(feature = "harte")]
+use nes_emulator::harte;
+use nes_emulator::library;
+use nes_emulator::messages::Catalog;
+use nes_emulator::opcodes;
 use nes_emulator::rom::ROM;
-use rand::Rng;
-use sdl2::event::Event;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.read_mem(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
+use nes_emulator::selftest;
+use nes_emulator::trace::{NestestTracer, TraceFilter, TraceOutput};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+// The catalog of the language picked by $NES_LANG or $LANG, with the user's
+// translation file on top when there is one
+fn load_catalog() -> Catalog {
+    let language = std::env::var("NES_LANG")
+        .or_else(|_| std::env::var("LANG"))
+        .unwrap_or_default();
+    let mut catalog = Catalog::for_language(&language);
+    let code = language.split(['_', '.', '-']).next().unwrap_or_default();
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(path) = config_dir.map(|dir| dir.join("nes-rust/messages").join(format!("{}.txt", code))) {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            if let Err(e) = catalog.load_translation(&text) {
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+    }
+    catalog
+}
+
+fn msg(key: &str, args: &[&dyn std::fmt::Display]) -> String {
+    CATALOG.get_or_init(load_catalog).format(key, args)
+}
+
+fn parse_addr(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| msg("invalid_address", &[&value]))
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => msg("unknown_panic", &[]),
+        },
+    }
+}
+
+// Where the debugger state of a program is kept between runs:
+// $XDG_DATA_HOME/nes-rust/debugger (~/.local/share by default), one file
+// per CRC-32 of the program (without the iNES header)
+fn debugger_state_path(raw: &[u8]) -> Option<PathBuf> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    let program = if ROM::is_ines(raw) { raw.get(16..)? } else { raw };
+    Some(data_dir.join("nes-rust/debugger").join(format!("{:08x}.txt", library::crc32(program))))
+}
+
+// Writes the cartridge as it is now, ROM patches included, to an iNES file
+fn export_cart(cpu: &CPU, path: &str) -> Result<String, String> {
+    let rom = cpu.bus.rom().ok_or_else(|| msg("no_cartridge", &[]))?;
+    std::fs::write(path.trim(), rom.to_ines()).map_err(|e| format!("{}: {}", path.trim(), e))?;
+    Ok(msg("cartridge_written", &[&path.trim()]))
+}
+
+// Runs debugger commands from stdin until 'run' or end of input
+fn debug_console(debugger: &mut Debugger, cpu: &mut CPU) -> Result<(), String> {
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        std::io::stdout().flush().map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(());
+        }
+        match line.trim() {
+            "" => continue,
+            "run" => return Ok(()),
+            command if command.starts_with("export-cart ") => match export_cart(cpu, &command[12..]) {
+                Ok(output) => println!("{}", output),
+                Err(e) => eprintln!("{}", e),
+            },
+            command => match debugger.execute(cpu, command) {
+                Ok(output) if debugger.is_running_to() => {
+                    println!("{}", output);
+                    return Ok(());
+                }
+                Ok(output) => println!("{}", output),
+                Err(e) => eprintln!("{}", e),
+            },
         }
-        frame_idx += 3;
-    }
-    update
- }
-
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
-   for event in event_pump.poll_iter() {
-       match event {
-           Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-               std::process::exit(0)
-           },
-           Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-               cpu.write_mem(0xff, 0x77);
-           },
-           Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-               cpu.write_mem(0xff, 0x73);
-           },
-           Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-               cpu.write_mem(0xff, 0x61);
-           },
-           Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-               cpu.write_mem(0xff, 0x64);
-           }
-           _ => {/* do nothing */}
-       }
-   }
-}
-
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
-    }
- }
-
-
-pub fn main() -> Result<(), String> {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Snake game", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
-        .position_centered()
-        .build().unwrap();
- 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
-    
-    let bus = Bus::new(ROM::from_file("roms/snake.nes").unwrap());
-    let mut cpu = CPU::new(bus);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut file: Option<&str> = None;
+    let mut load: u16 = 0x0600;
+    let mut entry: Option<u16> = None;
+    let mut ignore_mapper = false;
+    let mut debug = false;
+    let mut nestest_trace = false;
+    let mut trace_filters = vec![];
+    let mut color = false;
+    let mut expectations = vec![];
+    let mut profile = false;
+    let mut power_on = PowerOnConfig::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load" => load = parse_addr(args.next().ok_or_else(|| msg("needs_address", &[&"--load"]))?)?,
+            "--entry" => entry = Some(parse_addr(args.next().ok_or_else(|| msg("needs_address", &[&"--entry"]))?)?),
+            "--ignore-mapper" => ignore_mapper = true,
+            "--debug" => debug = true,
+            "--trace" => match args.next().map(String::as_str) {
+                Some("nestest") => nestest_trace = true,
+                _ => return Err(msg("trace_needs_format", &[])),
+            },
+            "--trace-filter" => {
+                trace_filters.push(TraceFilter::parse(args.next().ok_or_else(|| msg("needs_filter", &[&"--trace-filter"]))?)?)
+            }
+            "--color" => color = true,
+            "--profile" => profile = true,
+            "--random-ram" => {
+                let seed = args.next().ok_or_else(|| msg("needs_seed", &[&"--random-ram"]))?;
+                power_on.random_ram = Some(seed.parse().map_err(|_| msg("invalid_seed", &[seed]))?);
+            }
+            "--hardware-reset" => {
+                power_on.hardware_stack_pointer = true;
+                power_on.interrupt_disable = true;
+            }
+            "--expect" => expectations.push(parse_expectation(args.next().ok_or_else(|| msg("needs_assignment", &[&"--expect"]))?)?),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(msg("unexpected_argument", &[arg])),
+        }
+    }
+    let file = file.ok_or_else(|| msg("usage", &[]))?;
+    let raw = std::fs::read(file).map_err(|e| e.to_string())?;
+    let cartridge = ROM::is_ines(&raw);
+    let state_path = debugger_state_path(&raw);
+
+    let mut cpu = if cartridge {
+        let rom = if ignore_mapper {
+            ROM::new_with_mapper_fallback(raw)
+        } else {
+            ROM::new(raw)
+        };
+        let mut cpu = CPU::new(Bus::new(rom.map_err(|e| e.to_string())?));
+        cpu.power_on(power_on);
+        cpu
+    } else {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        cpu.power_on(power_on);
+        cpu.load_raw(&raw, load, entry.unwrap_or(load));
+        cpu
+    };
     cpu.reset();
+    if let Some(entry) = entry.filter(|_| cartridge) {
+        cpu.program_counter = entry;
+    }
+    let mut tracer = (nestest_trace || !trace_filters.is_empty() || color).then(NestestTracer::new);
+    if tracer.is_some() {
+        cpu.set_trace(false);
+    }
+    if nestest_trace {
+        cpu.stack_pointer = 0xFD;
+        cpu.status.status = 0x24;
+    }
+    let mut trace_output = TraceOutput::new(trace_filters, color);
+    let mut debugger = Debugger::new();
+    if let Some(saved) = state_path.as_ref().and_then(|path| std::fs::read_to_string(path).ok()) {
+        if let Err(e) = debugger.restore(&mut cpu, &saved) {
+            eprintln!("{}", e);
+        }
+        println!("{}", msg("debugger_state_restored", &[&state_path.as_ref().unwrap().display()]));
+    }
+    if profile {
+        cpu.start_profiling(true);
+    }
+    if debug {
+        debugger.attach(&mut cpu);
+        debug_console(&mut debugger, &mut cpu)?;
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+        let stop = cpu.execute_with_callback(|cpu| {
+            debugger.apply_freezes(cpu);
+            if let Some(line) = tracer.as_mut().and_then(|tracer| trace_output.push(cpu, tracer.trace(cpu))) {
+                println!("{}", line);
+            }
+            if let Some(reason) = debugger.stop_reason(cpu) {
+                println!("{}", reason);
+                if let Err(e) = debug_console(&mut debugger, cpu) {
+                    eprintln!("{}", e);
+                }
+            }
+        });
+        // Breakpoints and watchpoints stop execute(), open the console and
+        // carry on. Breakpoints for another PRG bank just carry on
+        let reason = match stop {
+            Ok(StopReason::Breakpoint(_)) => debugger.breakpoint_stop(&cpu),
+            Ok(StopReason::Watchpoint(hit)) => Some(hit.to_string()),
+            stop => break stop,
+        };
+        if let Some(reason) = reason {
+            println!("{}", reason);
+            if let Err(e) = debug_console(&mut debugger, &mut cpu) {
+                eprintln!("{}", e);
+            }
+        }
+    }));
+    if let Some(line) = trace_output.finish(&cpu) {
+        println!("{}", line);
+    }
+
+    if let Some(path) = &state_path {
+        let saved = debugger.save(&cpu);
+        if !saved.is_empty() || path.exists() {
+            let written = std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| std::fs::write(path, saved));
+            if let Err(e) = written {
+                eprintln!("{}", msg("debugger_state_not_saved", &[&path.display(), &e]));
+            }
+        }
+    }
+
+    let failure = match result {
+        Err(payload) => Some(panic_message(payload)),
+        Ok(Err(e)) => Some(e.to_string()),
+        Ok(Ok(_)) => None,
+    };
+    if let Some(reason) = failure {
+        let path = write_crash_dump(&cpu, &reason)?;
+        return Err(format!("{}\n{}", reason, msg("crash_report_written", &[&path.display()])));
+    }
+    println!("{}", cpu.snapshot());
+    if let Some(profile) = cpu.profile() {
+        print!("{}", profile.report(10));
+    }
+    let mismatches: Vec<String> = expectations
+        .iter()
+        .filter(|(addr, value)| cpu.peek_mem(*addr) != *value)
+        .map(|(addr, value)| {
+            let actual = cpu.peek_mem(*addr);
+            msg("expectation_failed", &[&format!("{:04X}", addr), &format!("{:02X}", actual), &format!("{:02X}", value)])
+        })
+        .collect();
+    if !mismatches.is_empty() {
+        return Err(mismatches.join("\n"));
+    }
+    Ok(())
+}
+
+// <addr>=<value>, both in hex
+fn parse_expectation(value: &str) -> Result<(u16, u8), String> {
+    let (addr, byte) = value.split_once('=').ok_or(msg("invalid_expectation", &[&value]))?;
+    let byte = parse_addr(byte)
+        .ok()
+        .and_then(|byte| u8::try_from(byte).ok())
+        .ok_or(msg("invalid_expectation", &[&value]))?;
+    Ok((parse_addr(addr)?, byte))
+}
+
+fn parse_range(value: &str) -> Result<(u16, u16), String> {
+    let (start, end) = value
+        .split_once("..")
+        .ok_or(msg("invalid_range", &[&value]))?;
+    Ok((parse_addr(start)?, parse_addr(end)?))
+}
+
+fn disasm(args: &[String]) -> Result<(), String> {
+    let mut file: Option<&str> = None;
+    let mut bank: Option<usize> = None;
+    let mut range: Option<(u16, u16)> = None;
+    let mut cdl: Option<Vec<u8>> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => {
+                let value = args.next().ok_or_else(|| msg("needs_number", &[&"--bank"]))?;
+                bank = Some(value.parse().map_err(|_| msg("invalid_bank", &[&value]))?);
+            }
+            "--range" => range = Some(parse_range(args.next().ok_or_else(|| msg("needs_range", &[&"--range"]))?)?),
+            "--cdl" => {
+                let path = args.next().ok_or_else(|| msg("needs_file", &[&"--cdl"]))?;
+                cdl = Some(std::fs::read(path).map_err(|e| e.to_string())?);
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(msg("unexpected_argument", &[arg])),
+        }
+    }
+    let rom = ROM::from_file(file.ok_or_else(|| msg("usage", &[]))?).map_err(|e| e.to_string())?;
+
+    // The last bank is fixed at $C000 (mirrored at $8000 for 16KB carts), the
+    // others switch in at $8000 on bank-switching mappers
+    let banks: Vec<&[u8]> = rom.prg_rom.chunks(PRG_BANK_SIZE).collect();
+    let bank_base = |n: usize| if n + 1 == banks.len() { 0xC000u16 } else { 0x8000 };
+
+    let mut labels = BTreeMap::new();
+    let last_bank = banks.last().ok_or_else(|| msg("no_prg_banks", &[]))?;
+    for (name, vector) in [("nmi", 0xFFFA), ("reset", 0xFFFC), ("irq", 0xFFFE)] {
+        let offset = vector - bank_base(banks.len() - 1) as usize;
+        let target = u16::from_le_bytes([last_bank[offset], last_bank[offset + 1]]);
+        labels.entry(target).or_insert_with(|| name.to_string());
+    }
+
+    let selected: Vec<usize> = match bank {
+        Some(n) if n < banks.len() => vec![n],
+        Some(n) => return Err(msg("bank_out_of_range", &[&n, &banks.len()])),
+        None => (0..banks.len()).collect(),
+    };
+    for n in selected {
+        let base = bank_base(n);
+        let last = base + (PRG_BANK_SIZE - 1) as u16;
+        let (start, end) = range.unwrap_or((base, last));
+        if start > last || end < base {
+            continue;
+        }
+        let (start, end) = (start.max(base), end.min(last));
+        let from = (start - base) as usize;
+        let to = (end - base) as usize + 1;
+        let bank_cdl = cdl
+            .as_ref()
+            .and_then(|cdl| cdl.get(n * PRG_BANK_SIZE + from..n * PRG_BANK_SIZE + to));
+
+        println!("; bank {} at ${:04X}", n, base);
+        for line in disassemble(&banks[n][from..to], start, &labels, bank_cdl) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn list_opcodes(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|arg| arg == "--json") {
+        println!("{}", opcodes::to_json());
+        return Ok(());
+    }
+    for op in opcodes::all() {
+        println!(
+            "{:02X}  {}{} {:<14} {} bytes, {} cycles  {:<6} {}",
+            op.opcode,
+            if op.official { " " } else { "*" },
+            op.label,
+            format!("{:?}", op.addressing_mode),
+            op.bytes,
+            op.cycles,
+            op.flags,
+            op.description,
+        );
+    }
+    Ok(())
+}
+
+fn parse_sample(value: &str) -> Result<SampleRef, String> {
+    let (address, length) = value
+        .split_once(':')
+        .ok_or(msg("invalid_sample", &[&value]))?;
+    Ok(SampleRef {
+        address: parse_addr(address)?,
+        length: parse_addr(length)? as usize,
+    })
+}
+
+fn extract_dpcm(args: &[String]) -> Result<(), String> {
+    let mut file: Option<&str> = None;
+    let mut samples = vec![];
+    let mut rate: u8 = 15;
+    let mut out = std::path::PathBuf::from(".");
 
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
-
-    cpu.execute_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
-        cpu.write_mem(0xfe, rng.gen_range(1..16));
- 
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sample" => samples.push(parse_sample(args.next().ok_or_else(|| msg("needs_sample", &[&"--sample"]))?)?),
+            "--rate" => {
+                let value = args.next().ok_or_else(|| msg("needs_number", &[&"--rate"]))?;
+                rate = value
+                    .parse()
+                    .ok()
+                    .filter(|rate| *rate < 16)
+                    .ok_or(msg("invalid_rate", &[&value]))?;
+            }
+            "--out" => out = args.next().ok_or_else(|| msg("needs_directory", &[&"--out"]))?.into(),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(msg("unexpected_argument", &[arg])),
         }
- 
-        ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    });
+    }
+    let rom = ROM::from_file(file.ok_or_else(|| msg("usage", &[]))?).map_err(|e| e.to_string())?;
+    if samples.is_empty() {
+        samples = dpcm::scan_sample_writes(&rom.prg_rom);
+    }
+    if samples.is_empty() {
+        return Err(msg("no_dpcm_samples", &[]));
+    }
+
+    let bus = Bus::new(rom);
+    for sample in samples {
+        let levels = dpcm::decode(&dpcm::read_sample(&bus, sample), 64);
+        let path = out.join(format!("dpcm-{:04X}-{}.wav", sample.address, sample.length));
+        std::fs::write(&path, dpcm::to_wav(&levels, dpcm::sample_rate(rate)))
+            .map_err(|e| e.to_string())?;
+        println!("${:04X} {:>5} bytes -> {}", sample.address, sample.length, path.display());
+    }
     Ok(())
 }
+
+fn export_cartridge(args: &[String]) -> Result<(), String> {
+    let mut files = vec![];
+    let mut pokes = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--poke" => pokes.push(parse_expectation(args.next().ok_or_else(|| msg("needs_assignment", &[&"--poke"]))?)?),
+            _ if files.len() < 2 => files.push(arg.as_str()),
+            _ => return Err(msg("unexpected_argument", &[arg])),
+        }
+    }
+    let [rom, out] = files[..] else {
+        return Err(msg("usage", &[]));
+    };
+    let rom = ROM::from_file(rom).map_err(|e| e.to_string())?;
+    let mut cpu = CPU::new(Bus::new(rom));
+    for (addr, value) in pokes {
+        cpu.write_mem(addr, value);
+    }
+    println!("{}", export_cart(&cpu, out)?);
+    Ok(())
+}
+
+fn scan_library(args: &[String]) -> Result<(), String> {
+    let mut dir: Option<&str> = None;
+    let mut out: Option<&str> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = Some(args.next().ok_or_else(|| msg("needs_file", &[&"--out"]))?),
+            _ if dir.is_none() => dir = Some(arg),
+            _ => return Err(msg("unexpected_argument", &[arg])),
+        }
+    }
+    let entries = library::scan(std::path::Path::new(dir.ok_or_else(|| msg("usage", &[]))?))?;
+    let json = library::to_json(&entries);
+    match out {
+        Some(path) => {
+            std::fs::write(path, json).map_err(|e| e.to_string())?;
+            println!("{}", msg("roms_indexed", &[&entries.len(), &path]));
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn run_selftest() -> Result<(), String> {
+    // Failing opcodes may panic, the matrix reports them
+    panic::set_hook(Box::new(|_| {}));
+    let report = selftest::run();
+    let _ = panic::take_hook();
+    println!("{}", report.matrix());
+    if !report.passed() {
+        return Err(msg("selftest_failed", &[]));
+    }
+    println!("{}", msg("selftest_passed", &[&report.results.len()]));
+    Ok(())
+}
+
+#[cfg(feature = "harte")]
+fn run_harte(args: &[String]) -> Result<(), String> {
+    let dir = args.first().ok_or_else(|| msg("usage", &[]))?;
+    // Failing cases may panic, the report counts them
+    panic::set_hook(Box::new(|_| {}));
+    let report = harte::run_dir(std::path::Path::new(dir));
+    let _ = panic::take_hook();
+    let report = report?;
+    println!("{}", report.summary());
+    if !report.passed() {
+        return Err(msg("harte_failed", &[]));
+    }
+    Ok(())
+}
+
+pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("opcodes") => list_opcodes(&args[1..]),
+        Some("dpcm") => extract_dpcm(&args[1..]),
+        Some("scan") => scan_library(&args[1..]),
+        Some("export-cart") => export_cartridge(&args[1..]),
+        Some("selftest") => run_selftest(),
+        #[cfg(feature = "harte")]
+        Some("harte") => run_harte(&args[1..]),
+        _ => Err(msg("usage", &[])),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
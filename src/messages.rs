@@ -0,0 +1,327 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::Display;
+
+// User-facing text of the frontends, looked up by key. "{}" placeholders are
+// filled in order. A language only has to translate some keys, the others
+// fall back to English.
+const ENGLISH: &[(&str, &str)] = &[
+    ("invalid_address", "Invalid address: {}"),
+    ("invalid_range", "Invalid range: {}"),
+    ("invalid_bank", "Invalid bank: {}"),
+    ("invalid_sample", "Invalid sample: {}"),
+    ("invalid_rate", "Invalid rate: {}"),
+    ("unexpected_argument", "Unexpected argument: {}"),
+    ("trace_needs_format", "--trace needs a format (nestest)"),
+    ("bank_out_of_range", "Bank {} out of range, ROM has {} banks"),
+    ("cartridge_written", "Cartridge written to {}"),
+    ("debugger_state_restored", "Debugger state restored from {}"),
+    ("debugger_state_not_saved", "Could not save the debugger state to {}: {}"),
+    ("crash_report_written", "Crash report written to {}"),
+    ("no_dpcm_samples", "No DPCM samples found, pass them with --sample"),
+    ("roms_indexed", "Indexed {} ROMs into {}"),
+    ("selftest_failed", "Self-test failed"),
+    ("selftest_passed", "All {} opcodes passed"),
+    ("harte_failed", "Some test vectors failed"),
+    ("invalid_expectation", "Invalid expectation: {}"),
+    ("expectation_failed", "${} is ${}, expected ${}"),
+    ("invalid_seed", "Invalid seed: {}"),
+    ("needs_address", "{} needs an address"),
+    ("needs_number", "{} needs a number"),
+    ("needs_range", "{} needs a range"),
+    ("needs_file", "{} needs a file"),
+    ("needs_directory", "{} needs a directory"),
+    ("needs_filter", "{} needs a filter"),
+    ("needs_seed", "{} needs a seed"),
+    ("needs_assignment", "{} needs <addr>=<value>"),
+    ("needs_sample", "{} needs <addr>:<length>"),
+    ("no_cartridge", "No cartridge loaded"),
+    ("no_prg_banks", "ROM has no PRG banks"),
+    ("unknown_panic", "Unknown panic"),
+    ("usage", USAGE_ENGLISH),
+];
+
+const ITALIAN: &[(&str, &str)] = &[
+    ("invalid_address", "Indirizzo non valido: {}"),
+    ("invalid_range", "Intervallo non valido: {}"),
+    ("invalid_bank", "Banco non valido: {}"),
+    ("invalid_sample", "Campione non valido: {}"),
+    ("invalid_rate", "Frequenza non valida: {}"),
+    ("unexpected_argument", "Argomento inatteso: {}"),
+    ("trace_needs_format", "--trace richiede un formato (nestest)"),
+    ("bank_out_of_range", "Banco {} fuori intervallo, la ROM ha {} banchi"),
+    ("cartridge_written", "Cartuccia salvata in {}"),
+    ("debugger_state_restored", "Stato del debugger ripristinato da {}"),
+    ("debugger_state_not_saved", "Impossibile salvare lo stato del debugger in {}: {}"),
+    ("crash_report_written", "Rapporto del crash salvato in {}"),
+    ("no_dpcm_samples", "Nessun campione DPCM trovato, indicali con --sample"),
+    ("roms_indexed", "{} ROM indicizzate in {}"),
+    ("selftest_failed", "Self-test fallito"),
+    ("selftest_passed", "Tutti i {} opcode superati"),
+    ("harte_failed", "Alcuni vettori di test sono falliti"),
+    ("invalid_expectation", "Condizione non valida: {}"),
+    ("expectation_failed", "${} vale ${}, atteso ${}"),
+    ("invalid_seed", "Seme non valido: {}"),
+    ("needs_address", "{} richiede un indirizzo"),
+    ("needs_number", "{} richiede un numero"),
+    ("needs_range", "{} richiede un intervallo"),
+    ("needs_file", "{} richiede un file"),
+    ("needs_directory", "{} richiede una cartella"),
+    ("needs_filter", "{} richiede un filtro"),
+    ("needs_seed", "{} richiede un seme"),
+    ("needs_assignment", "{} richiede <ind>=<valore>"),
+    ("needs_sample", "{} richiede <ind>:<lunghezza>"),
+    ("no_cartridge", "Nessuna cartuccia caricata"),
+    ("no_prg_banks", "La ROM non ha banchi PRG"),
+    ("unknown_panic", "Panic sconosciuto"),
+    ("usage", USAGE_ITALIAN),
+];
+
+const USAGE_ENGLISH: &str = "Usage:
+  nes run <file> [--load <addr>] [--entry <addr>] [--ignore-mapper] [--debug]
+          [--trace nestest] [--trace-filter <filter>]... [--color]
+          [--expect <addr>=<value>]... [--profile]
+          [--random-ram <seed>] [--hardware-reset]
+  nes disasm <rom> [--bank <n>] [--range <addr>..<addr>] [--cdl <file>]
+  nes opcodes [--json]
+  nes scan <dir> [--out <file>]
+  nes export-cart <rom> <out.nes> [--poke <addr>=<value>]...
+  nes selftest
+  nes harte <dir>                   (with --features harte)
+  nes dpcm <rom> [--sample <addr>:<length>]... [--rate <0-15>] [--out <dir>]
+
+Files starting with an iNES header are loaded as cartridges. Anything else is
+treated as a raw 6502 binary, copied at --load (default $0600) with the reset
+vector pointing to --entry (default: the load address). --ignore-mapper loads
+cartridges with an unsupported mapper as mapper 0. --debug reads debugger
+commands (poke, freeze, break...) from stdin until 'run', then starts the
+program. The console opens again whenever a breakpoint or a watchpoint is
+hit, or when a 'to', 'finish' or 'frame' command given there completes.
+'back' there undoes up to the last 1000 instructions ('undo <depth>' changes
+how many).
+'export-cart <out.nes>' there saves the cartridge with any bytes poked into
+PRG ROM as an iNES file.
+Breakpoints, watchpoints and freezes are saved per program in
+$XDG_DATA_HOME/nes-rust/debugger and restored on the next run.
+
+--random-ram fills the 2KB of RAM with bytes from <seed> at power-on, like
+real hardware, instead of zeros. --hardware-reset makes reset set the I flag
+and leave SP at $FD.
+
+--expect checks a byte of memory once the program stops and exits with code 1
+when it differs, e.g. --expect '$0200=$01' for a test that stores its result
+there. Repeat it to check several bytes.
+
+--profile prints the opcodes and instruction addresses that took the most
+cycles once the program stops.
+
+--trace nestest prints every instruction in the nestest.log format, starting
+from the power-up state that log assumes (SP=$FD, P=$24). For cartridges,
+--entry overrides the reset vector: run nestest.nes with --entry '$C000' to
+diff against the golden log.
+
+--trace-filter keeps only some of those lines: 'calls' (JSR/RTS/RTI/BRK),
+'branches' (branches and JMP), 'range:<addr>..<addr>' (instructions reading
+or writing there) or 'a' (instructions changing A). Repeat it to keep lines
+matching any of them. --color highlights calls, branches and unofficial
+opcodes. Both print the nestest format without --trace, but keep the reset
+state.
+
+disasm prints 16KB PRG banks at their CPU addresses, the last one at $C000
+and the others at $8000, labelling the NMI/RESET/IRQ vector targets. An FCEUX
+.cdl file marks data-only bytes.
+
+dpcm decodes DMC samples to WAV files in --out (default: current directory).
+Samples are found by scanning PRG code for $4012/$4013 writes, unless given
+with --sample (CPU address and length in bytes, e.g. --sample '$C000:$81').
+
+scan indexes every iNES ROM under a directory as JSON (path, CRC-32 of the
+data after the header, mapper, sizes, compatibility), to stdout or --out.
+
+export-cart writes <rom> back to <out.nes> as an iNES file after applying each
+--poke through the bus, e.g. --poke '$C000=$EA' to patch PRG ROM.
+
+selftest runs every opcode but KIL, unofficial ones included, in every
+addressing mode against a reference model, cycles included, and prints a
+pass/fail matrix. Include it in bug reports.
+
+Messages follow $NES_LANG, or $LANG (English and Italian are built in).
+$XDG_CONFIG_HOME/nes-rust/messages/<lang>.txt (~/.config by default) can
+translate or reword them with 'key = text' lines.
+
+harte runs the ProcessorTests single-step vectors (one xx.json per opcode,
+e.g. nes6502/v1) in a directory and prints the pass rate of every opcode.";
+
+const USAGE_ITALIAN: &str = "Uso:
+  nes run <file> [--load <ind>] [--entry <ind>] [--ignore-mapper] [--debug]
+          [--trace nestest] [--trace-filter <filtro>]... [--color]
+          [--expect <ind>=<valore>]... [--profile]
+          [--random-ram <seme>] [--hardware-reset]
+  nes disasm <rom> [--bank <n>] [--range <ind>..<ind>] [--cdl <file>]
+  nes opcodes [--json]
+  nes scan <dir> [--out <file>]
+  nes export-cart <rom> <out.nes> [--poke <ind>=<valore>]...
+  nes selftest
+  nes harte <dir>                   (con --features harte)
+  nes dpcm <rom> [--sample <ind>:<lunghezza>]... [--rate <0-15>] [--out <dir>]
+
+I file che iniziano con un header iNES sono caricati come cartucce. Il resto è
+trattato come un binario 6502 grezzo, copiato a --load (predefinito $0600) con
+il vettore di reset che punta a --entry (predefinito: l'indirizzo di
+caricamento). --ignore-mapper carica le cartucce con un mapper non supportato
+come mapper 0. --debug legge i comandi del debugger (poke, freeze, break...)
+da stdin fino a 'run', poi avvia il programma. La console si riapre a ogni
+breakpoint o watchpoint raggiunto, o quando un comando 'to', 'finish' o
+'frame' dato lì termina. 'back' lì annulla fino alle ultime 1000 istruzioni
+('undo <profondità>' cambia quante). 'export-cart <out.nes>' lì salva la
+cartuccia come file iNES, compresi i byte modificati nella PRG ROM.
+Breakpoint, watchpoint e freeze sono salvati per programma in
+$XDG_DATA_HOME/nes-rust/debugger e ripristinati all'avvio successivo.
+
+--random-ram riempie i 2KB di RAM con byte derivati da <seme> all'accensione,
+come l'hardware reale, invece che con zeri. --hardware-reset fa sì che il
+reset imposti il flag I e lasci SP a $FD.
+
+--expect controlla un byte di memoria quando il programma si ferma ed esce con
+codice 1 se è diverso, es. --expect '$0200=$01' per un test che salva lì il
+suo risultato. Ripetilo per controllare più byte.
+
+--profile stampa gli opcode e gli indirizzi delle istruzioni che hanno
+richiesto più cicli quando il programma si ferma.
+
+--trace nestest stampa ogni istruzione nel formato di nestest.log, partendo
+dallo stato di accensione che quel log presuppone (SP=$FD, P=$24). Per le
+cartucce, --entry sostituisce il vettore di reset: esegui nestest.nes con
+--entry '$C000' per confrontarlo con il log di riferimento.
+
+--trace-filter tiene solo alcune di quelle righe: 'calls' (JSR/RTS/RTI/BRK),
+'branches' (salti condizionati e JMP), 'range:<ind>..<ind>' (istruzioni che
+leggono o scrivono lì) o 'a' (istruzioni che cambiano A). Ripetilo per tenere
+le righe che corrispondono a uno qualsiasi. --color evidenzia chiamate, salti
+e opcode non ufficiali. Entrambi stampano il formato nestest anche senza
+--trace, ma mantengono lo stato di reset.
+
+disasm stampa i banchi PRG da 16KB ai loro indirizzi CPU, l'ultimo a $C000 e
+gli altri a $8000, etichettando le destinazioni dei vettori NMI/RESET/IRQ. Un
+file .cdl di FCEUX indica i byte di soli dati.
+
+dpcm decodifica i campioni DMC in file WAV in --out (predefinito: la cartella
+corrente). I campioni sono cercati nel codice PRG tra le scritture a
+$4012/$4013, a meno che siano indicati con --sample (indirizzo CPU e
+lunghezza in byte, es. --sample '$C000:$81').
+
+scan indicizza in JSON ogni ROM iNES sotto una cartella (percorso, CRC-32 dei
+dati dopo l'header, mapper, dimensioni, compatibilità), su stdout o in --out.
+
+export-cart riscrive <rom> in <out.nes> come file iNES dopo aver applicato
+ogni --poke attraverso il bus, es. --poke '$C000=$EA' per modificare la PRG
+ROM.
+
+selftest esegue ogni opcode tranne KIL, compresi quelli non ufficiali, in ogni
+modo di indirizzamento confrontandolo con un modello di riferimento, cicli
+compresi, e stampa una matrice di esiti. Allegala alle segnalazioni di bug.
+
+I messaggi seguono $NES_LANG, o $LANG (inglese e italiano sono inclusi).
+$XDG_CONFIG_HOME/nes-rust/messages/<lingua>.txt (~/.config come predefinito)
+può tradurli o riformularli con righe 'chiave = testo'.
+
+harte esegue i vettori single-step di ProcessorTests (un xx.json per opcode,
+es. nes6502/v1) in una cartella e stampa la percentuale di successo di ogni
+opcode.";
+
+pub struct Catalog {
+    messages: BTreeMap<String, String>,
+}
+
+impl Catalog {
+    pub fn english() -> Self {
+        Self::from_table(ENGLISH)
+    }
+
+    // Built-in messages for a language code like "it" or "it_IT.UTF-8",
+    // English for unknown languages
+    pub fn for_language(language: &str) -> Self {
+        let mut catalog = Self::english();
+        let code = language.split(['_', '.', '-']).next().unwrap_or_default();
+        if code == "it" {
+            catalog.messages.extend(Self::from_table(ITALIAN).messages);
+        }
+        catalog
+    }
+
+    fn from_table(table: &[(&str, &str)]) -> Self {
+        Self {
+            messages: table.iter().map(|(key, text)| (key.to_string(), text.to_string())).collect(),
+        }
+    }
+
+    // Overrides messages with a user translation: "key = text" lines, empty
+    // lines and lines starting with '#' are skipped
+    pub fn load_translation(&mut self, text: &str) -> Result<(), String> {
+        for (n, line) in text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, message) = line
+                .split_once('=')
+                .ok_or(format!("Line {}: expected \"key = text\"", n))?;
+            self.messages.insert(key.trim().to_string(), message.trim().to_string());
+        }
+        Ok(())
+    }
+
+    // The message for `key` with its placeholders filled. Unknown keys come
+    // back as is, so a typo shows up instead of an empty line
+    pub fn format(&self, key: &str, args: &[&dyn Display]) -> String {
+        let Some(template) = self.messages.get(key) else {
+            return key.to_string();
+        };
+        let mut args = args.iter();
+        let mut parts = template.split("{}");
+        let mut out = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            match args.next() {
+                Some(arg) => out.push_str(&format!("{}", arg)),
+                None => out.push_str("{}"),
+            }
+            out.push_str(part);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let catalog = Catalog::english();
+        assert_eq!(catalog.format("bank_out_of_range", &[&3, &2]), "Bank 3 out of range, ROM has 2 banks");
+        assert_eq!(catalog.format("invalid_address", &[]), "Invalid address: {}");
+        assert_eq!(catalog.format("no_such_message", &[]), "no_such_message");
+    }
+
+    #[test]
+    fn test_languages() {
+        let italian = Catalog::for_language("it_IT.UTF-8");
+        assert_eq!(italian.format("cartridge_written", &[&"out.nes"]), "Cartuccia salvata in out.nes");
+        assert_eq!(Catalog::for_language("fr").format("selftest_failed", &[]), "Self-test failed");
+        assert_eq!(italian.format("needs_number", &[&"--bank"]), "--bank richiede un numero");
+        assert!(italian.format("usage", &[]).starts_with("Uso:"));
+        // Every key of every language exists in English
+        assert!(ITALIAN.iter().all(|(key, _)| ENGLISH.iter().any(|(english, _)| english == key)));
+    }
+
+    #[test]
+    fn test_load_translation() {
+        let mut catalog = Catalog::english();
+        catalog
+            .load_translation("# Deutsch\n\nselftest_failed = Selbsttest fehlgeschlagen\n")
+            .unwrap();
+        assert_eq!(catalog.format("selftest_failed", &[]), "Selbsttest fehlgeschlagen");
+        assert_eq!(catalog.format("harte_failed", &[]), "Some test vectors failed");
+        assert!(catalog.load_translation("selftest_failed").is_err());
+    }
+}
@@ -1,12 +1,97 @@
 use crate::cpu::AddressingMode;
-use std::collections::HashMap;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Instruction {
+    ADC,
+    ALR,
+    ANC,
+    AND,
+    ARR,
+    ASL,
+    AXS,
+    BCC,
+    BCS,
+    BEQ,
+    BIT,
+    BMI,
+    BNE,
+    BPL,
+    BRK,
+    BVC,
+    BVS,
+    CLC,
+    CLD,
+    CLI,
+    CLV,
+    CMP,
+    CPX,
+    CPY,
+    DCP,
+    DEC,
+    DEX,
+    DEY,
+    EOR,
+    INC,
+    INX,
+    INY,
+    ISB,
+    JMP,
+    JSR,
+    KIL,
+    LAX,
+    LDA,
+    LDX,
+    LDY,
+    LSR,
+    NOP,
+    ORA,
+    PHA,
+    PHP,
+    PLA,
+    PLP,
+    RLA,
+    ROL,
+    ROR,
+    RRA,
+    RTI,
+    RTS,
+    SAX,
+    SBC,
+    SEC,
+    SED,
+    SEI,
+    SLO,
+    SRE,
+    STA,
+    STX,
+    STY,
+    TAX,
+    TAY,
+    TSX,
+    TXA,
+    TXS,
+    TYA,
+}
+
+#[derive(Debug)]
 pub struct OpCode {
     pub opcode: u8,
     pub label: &'static str,
+    pub instruction: Instruction,
     pub bytes: u8,
     pub cycles: u16,
     pub addressing_mode: AddressingMode,
+    pub official: bool,
+    // Flags the instruction may change, in NV-BDIZC order
+    pub flags: &'static str,
+    pub description: &'static str,
 }
 
 impl OpCode {
@@ -17,14 +102,142 @@ impl OpCode {
         cycles: u16,
         addressing_mode: AddressingMode,
     ) -> Self {
+        let (instruction, description, flags, official) = mnemonic_info(label);
         Self {
             opcode,
             label,
+            instruction,
             bytes,
             cycles,
             addressing_mode,
+            official,
+            flags,
+            description,
         }
     }
+
+    // Undocumented duplicate of an official instruction
+    fn unofficial(
+        opcode: u8,
+        label: &'static str,
+        bytes: u8,
+        cycles: u16,
+        addressing_mode: AddressingMode,
+    ) -> Self {
+        Self {
+            official: false,
+            ..Self::new(opcode, label, bytes, cycles, addressing_mode)
+        }
+    }
+}
+
+impl OpCode {
+    // Indexed reads take one more cycle when the index crosses a page, the
+    // CPU has to fix up the high byte first. Writes and read-modify-writes
+    // always spend that cycle, it's already in their count
+    pub fn page_cross_penalty(&self) -> bool {
+        matches!(
+            self.addressing_mode,
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+        ) && matches!(
+            self.instruction,
+            Instruction::ADC
+                | Instruction::AND
+                | Instruction::CMP
+                | Instruction::EOR
+                | Instruction::LAX
+                | Instruction::LDA
+                | Instruction::LDX
+                | Instruction::LDY
+                | Instruction::NOP
+                | Instruction::ORA
+                | Instruction::SBC
+        )
+    }
+}
+
+// "LDA Immediate", unofficial opcodes marked with a star like in nestest logs
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let star = if self.official { "" } else { "*" };
+        write!(f, "{}{} {:?}", star, self.label, self.addressing_mode)
+    }
+}
+
+// Instruction, description, affected flags and whether the mnemonic is documented
+fn mnemonic_info(label: &str) -> (Instruction, &'static str, &'static str, bool) {
+    match label {
+        "ADC" => (Instruction::ADC, "Add with Carry", "NVZC", true),
+        "ALR" => (Instruction::ALR, "AND then Logical Shift Right", "NZC", false),
+        "ANC" => (Instruction::ANC, "AND then copy Negative to Carry", "NZC", false),
+        "AND" => (Instruction::AND, "Logical AND", "NZ", true),
+        "ARR" => (Instruction::ARR, "AND then Rotate Right", "NVZC", false),
+        "ASL" => (Instruction::ASL, "Arithmetic Shift Left", "NZC", true),
+        "AXS" => (Instruction::AXS, "X = (A AND X) minus operand", "NZC", false),
+        "BCC" => (Instruction::BCC, "Branch if Carry Clear", "", true),
+        "BCS" => (Instruction::BCS, "Branch if Carry Set", "", true),
+        "BEQ" => (Instruction::BEQ, "Branch if Equal", "", true),
+        "BIT" => (Instruction::BIT, "Bit Test", "NVZ", true),
+        "BMI" => (Instruction::BMI, "Branch if Minus", "", true),
+        "BNE" => (Instruction::BNE, "Branch if Not Equal", "", true),
+        "BPL" => (Instruction::BPL, "Branch if Positive", "", true),
+        "BRK" => (Instruction::BRK, "Force Interrupt", "BI", true),
+        "BVC" => (Instruction::BVC, "Branch if Overflow Clear", "", true),
+        "BVS" => (Instruction::BVS, "Branch if Overflow Set", "", true),
+        "CLC" => (Instruction::CLC, "Clear Carry Flag", "C", true),
+        "CLD" => (Instruction::CLD, "Clear Decimal Mode", "D", true),
+        "CLI" => (Instruction::CLI, "Clear Interrupt Disable", "I", true),
+        "CLV" => (Instruction::CLV, "Clear Overflow Flag", "V", true),
+        "CMP" => (Instruction::CMP, "Compare", "NZC", true),
+        "CPX" => (Instruction::CPX, "Compare X Register", "NZC", true),
+        "CPY" => (Instruction::CPY, "Compare Y Register", "NZC", true),
+        "DCP" => (Instruction::DCP, "Decrement Memory then Compare", "NZC", false),
+        "DEC" => (Instruction::DEC, "Decrement Memory", "NZ", true),
+        "DEX" => (Instruction::DEX, "Decrement X Register", "NZ", true),
+        "DEY" => (Instruction::DEY, "Decrement Y Register", "NZ", true),
+        "EOR" => (Instruction::EOR, "Exclusive OR", "NZ", true),
+        "INC" => (Instruction::INC, "Increment Memory", "NZ", true),
+        "INX" => (Instruction::INX, "Increment X Register", "NZ", true),
+        "INY" => (Instruction::INY, "Increment Y Register", "NZ", true),
+        "ISB" => (Instruction::ISB, "Increment Memory then Subtract with Carry", "NVZC", false),
+        "JMP" => (Instruction::JMP, "Jump", "", true),
+        "JSR" => (Instruction::JSR, "Jump to Subroutine", "", true),
+        "KIL" => (Instruction::KIL, "Halt the CPU", "", false),
+        "LAX" => (Instruction::LAX, "Load Accumulator and X Register", "NZ", false),
+        "LDA" => (Instruction::LDA, "Load Accumulator", "NZ", true),
+        "LDX" => (Instruction::LDX, "Load X Register", "NZ", true),
+        "LDY" => (Instruction::LDY, "Load Y Register", "NZ", true),
+        "LSR" => (Instruction::LSR, "Logical Shift Right", "NZC", true),
+        "NOP" => (Instruction::NOP, "No Operation", "", true),
+        "ORA" => (Instruction::ORA, "Logical Inclusive OR", "NZ", true),
+        "PHA" => (Instruction::PHA, "Push Accumulator", "", true),
+        "PHP" => (Instruction::PHP, "Push Processor Status", "", true),
+        "PLA" => (Instruction::PLA, "Pull Accumulator", "NZ", true),
+        "PLP" => (Instruction::PLP, "Pull Processor Status", "NVDIZC", true),
+        "RLA" => (Instruction::RLA, "Rotate Left then AND", "NZC", false),
+        "ROL" => (Instruction::ROL, "Rotate Left", "NZC", true),
+        "ROR" => (Instruction::ROR, "Rotate Right", "NZC", true),
+        "RRA" => (Instruction::RRA, "Rotate Right then Add with Carry", "NVZC", false),
+        "RTI" => (Instruction::RTI, "Return from Interrupt", "NVDIZC", true),
+        "RTS" => (Instruction::RTS, "Return from Subroutine", "", true),
+        "SAX" => (Instruction::SAX, "Store Accumulator AND X Register", "", false),
+        "SBC" => (Instruction::SBC, "Subtract with Carry", "NVZC", true),
+        "SEC" => (Instruction::SEC, "Set Carry Flag", "C", true),
+        "SED" => (Instruction::SED, "Set Decimal Flag", "D", true),
+        "SEI" => (Instruction::SEI, "Set Interrupt Disable", "I", true),
+        "SLO" => (Instruction::SLO, "Arithmetic Shift Left then OR", "NZC", false),
+        "SRE" => (Instruction::SRE, "Logical Shift Right then Exclusive OR", "NZC", false),
+        "STA" => (Instruction::STA, "Store Accumulator", "", true),
+        "STX" => (Instruction::STX, "Store X Register", "", true),
+        "STY" => (Instruction::STY, "Store Y Register", "", true),
+        "TAX" => (Instruction::TAX, "Transfer Accumulator to X", "NZ", true),
+        "TAY" => (Instruction::TAY, "Transfer Accumulator to Y", "NZ", true),
+        "TSX" => (Instruction::TSX, "Transfer Stack Pointer to X", "NZ", true),
+        "TXA" => (Instruction::TXA, "Transfer X to Accumulator", "NZ", true),
+        "TXS" => (Instruction::TXS, "Transfer X to Stack Pointer", "", true),
+        "TYA" => (Instruction::TYA, "Transfer Y to Accumulator", "NZ", true),
+        _ => panic!("Missing description for opcode {}", label),
+    }
 }
 
 lazy_static! {
@@ -117,9 +330,21 @@ lazy_static! {
         OpCode::new(0xBE, "LDX", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_Y),
         OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_Y),
+        OpCode::new(0xBC, "LDY", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::new(0x02, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x12, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x22, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x32, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x42, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x52, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x62, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x72, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0x92, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0xB2, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0xD2, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
+        OpCode::new(0xF2, "KIL", 1, 0 /* halts the CPU */, AddressingMode::NoneAddressing),
         OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
@@ -161,11 +386,10 @@ lazy_static! {
         OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8D, "STA", 2, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 2, 5, AddressingMode::Absolute_X),
+        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
         OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
         OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
@@ -173,7 +397,7 @@ lazy_static! {
         OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
         OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
         OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
         OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
@@ -181,16 +405,163 @@ lazy_static! {
         OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
+        // Unofficial opcodes, stable on every 6502 revision
+        OpCode::new(0x4B, "ALR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x0B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x2B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x6B, "ARR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xCB, "AXS", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xC7, "DCP", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xD7, "DCP", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xCF, "DCP", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xDF, "DCP", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0xDB, "DCP", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xC3, "DCP", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xD3, "DCP", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0xE7, "ISB", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xF7, "ISB", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xEF, "ISB", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xFF, "ISB", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0xFB, "ISB", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xE3, "ISB", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xF3, "ISB", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0xA7, "LAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xB7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0xAF, "LAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xBF, "LAX", 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new(0xA3, "LAX", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0xB3, "LAX", 2, 5, AddressingMode::Indirect_Y),
+        OpCode::new(0x27, "RLA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x2F, "RLA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3F, "RLA", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x3B, "RLA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x23, "RLA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x67, "RRA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x6F, "RRA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7F, "RRA", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x7B, "RRA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x63, "RRA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x87, "SAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x97, "SAX", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0x8F, "SAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x83, "SAX", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x07, "SLO", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0F, "SLO", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x1F, "SLO", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x1B, "SLO", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x03, "SLO", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x47, "SRE", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x4F, "SRE", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x5F, "SRE", 3, 7, AddressingMode::Absolute_X),
+        OpCode::new(0x5B, "SRE", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x43, "SRE", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::unofficial(0xEB, "SBC", 2, 2, AddressingMode::Immediate),
+        // NOPs that read their operand and throw it away (DOP/TOP)
+        OpCode::unofficial(0x1A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x3A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x5A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x7A, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0xDA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0xFA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::unofficial(0x80, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::unofficial(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::unofficial(0x89, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::unofficial(0xC2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::unofficial(0xE2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::unofficial(0x04, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::unofficial(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::unofficial(0x64, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::unofficial(0x14, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x34, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x74, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xD4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0xF4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::unofficial(0x0C, "NOP", 3, 4, AddressingMode::Absolute),
+        OpCode::unofficial(0x1C, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::unofficial(0x3C, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::unofficial(0x5C, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::unofficial(0x7C, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::unofficial(0xDC, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
+        OpCode::unofficial(0xFC, "NOP", 3, 4 /* +1 if page is crossed */, AddressingMode::Absolute_X),
     ];
 
-    pub static ref CPU_OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
+    pub static ref CPU_OPCODES_MAP: BTreeMap<u8, &'static OpCode> = {
+        let mut map = BTreeMap::new();
         for op in CPU_OPCODES.iter() {
             map.insert(op.opcode, op);
         }
         map
     };
+
+    // Decoding table indexed by opcode byte, None for undefined opcodes
+    pub static ref CPU_OPCODE_TABLE: [Option<&'static OpCode>; 256] = {
+        let mut table = [None; 256];
+        for op in CPU_OPCODES.iter() {
+            table[op.opcode as usize] = Some(op);
+        }
+        table
+    };
 }
 
 #[derive(Debug, Clone)]
 pub struct OpCodeNotFound;
+
+pub fn all() -> &'static [OpCode] {
+    &CPU_OPCODES
+}
+
+// The whole opcode table as a JSON array, for external tools
+pub fn to_json() -> String {
+    let entries: Vec<String> = all()
+        .iter()
+        .map(|op| {
+            format!(
+                "  {{\"opcode\": {}, \"mnemonic\": \"{}\", \"mode\": \"{:?}\", \"bytes\": {}, \"cycles\": {}, \"official\": {}, \"flags\": \"{}\", \"description\": \"{}\"}}",
+                op.opcode, op.label, op.addressing_mode, op.bytes, op.cycles, op.official, op.flags, op.description
+            )
+        })
+        .collect();
+    format!("[\n{}\n]", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcodes_are_unique() {
+        assert_eq!(CPU_OPCODES_MAP.len(), all().len());
+    }
+
+    #[test]
+    fn test_opcode_table() {
+        assert_eq!(CPU_OPCODE_TABLE.iter().flatten().count(), all().len());
+        let lda = CPU_OPCODE_TABLE[0xA9].unwrap();
+        assert_eq!((lda.label, lda.instruction), ("LDA", Instruction::LDA));
+        assert!(CPU_OPCODE_TABLE[0x8B].is_none());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(CPU_OPCODES_MAP[&0xA9].to_string(), "LDA Immediate");
+        assert_eq!(CPU_OPCODES_MAP[&0xA7].to_string(), "*LAX ZeroPage");
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = to_json();
+        assert!(json.starts_with("[\n"));
+        assert!(json.contains(
+            "{\"opcode\": 105, \"mnemonic\": \"ADC\", \"mode\": \"Immediate\", \"bytes\": 2, \"cycles\": 2, \"official\": true, \"flags\": \"NVZC\", \"description\": \"Add with Carry\"}"
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+// What the Bus needs from a PPU. Implementations are attached with
+// Bus::attach_ppu, so different renderers can be swapped and compared
+// without touching the Bus.
+pub trait Ppu {
+    // $2000-$2007, `register` being the address mirrored down to 0-7
+    fn read_register(&mut self, register: u8) -> u8;
+
+    // Side-effect-free read for debuggers, tracers and disassemblers: what
+    // read_register would return, without clearing the VBlank flag and the
+    // write latch on PPUSTATUS or moving the PPUDATA read buffer and address
+    fn peek_register(&self, register: u8) -> u8;
+
+    fn write_register(&mut self, register: u8, value: u8);
+
+    // The page copied by an OAM DMA ($4014 write)
+    fn write_oam(&mut self, data: &[u8; 256]);
+
+    // Runs for `cycles` CPU cycles (3 dots each). Returns true when it pulls
+    // NMI, i.e. VBlank started with NMIs enabled
+    fn tick(&mut self, cycles: u16) -> bool;
+}
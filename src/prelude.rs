@@ -0,0 +1,15 @@
+// The types a frontend needs to load a program, run it and attach devices.
+// These names are kept stable across releases, unlike the module paths
+// behind them, so frontends should import them from here:
+//
+//     use nes_emulator::prelude::*;
+pub use crate::apu::Apu;
+pub use crate::bus::Bus;
+pub use crate::control::ExecutionControl;
+pub use crate::cpu::{
+    CpuError, CpuSnapshot, CpuState, HookId, HookPoint, InstructionEvent, Mem, MemAccess, PowerOnConfig, StopReason, CPU,
+};
+pub use crate::irq::{IrqLine, IrqSource};
+pub use crate::ppu::Ppu;
+pub use crate::rom::{Mirroring, RomError, ROM};
+pub use crate::status_flags::{ProcessorStatus, StatusFlag};
@@ -0,0 +1,144 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::opcodes;
+
+// Executions of an opcode or an instruction address, and the cycles they took
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+impl Counts {
+    fn add(&mut self, cycles: u16) {
+        self.executions += 1;
+        self.cycles += cycles as u64;
+    }
+}
+
+// Where a program spends its time: counts per opcode, and per instruction
+// address when asked for. Cycles are the instruction's own, without DMA
+// stalls and interrupt sequences
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    opcodes: [Counts; 256],
+    addresses: Option<BTreeMap<u16, Counts>>,
+}
+
+impl Profile {
+    pub fn new(per_address: bool) -> Self {
+        Self {
+            opcodes: [Counts::default(); 256],
+            addresses: per_address.then(BTreeMap::new),
+        }
+    }
+
+    pub fn record(&mut self, address: u16, opcode: u8, cycles: u16) {
+        self.opcodes[opcode as usize].add(cycles);
+        if let Some(addresses) = &mut self.addresses {
+            addresses.entry(address).or_default().add(cycles);
+        }
+    }
+
+    pub fn opcode(&self, opcode: u8) -> Counts {
+        self.opcodes[opcode as usize]
+    }
+
+    // None when addresses aren't profiled
+    pub fn address(&self, address: u16) -> Option<Counts> {
+        let addresses = self.addresses.as_ref()?;
+        Some(addresses.get(&address).copied().unwrap_or_default())
+    }
+
+    pub fn total(&self) -> Counts {
+        self.opcodes.iter().fold(Counts::default(), |total, counts| Counts {
+            executions: total.executions + counts.executions,
+            cycles: total.cycles + counts.cycles,
+        })
+    }
+
+    // Executed opcodes, most cycles first
+    pub fn hottest_opcodes(&self) -> Vec<(u8, Counts)> {
+        let mut opcodes: Vec<(u8, Counts)> = (0..=255u8)
+            .map(|code| (code, self.opcodes[code as usize]))
+            .filter(|(_, counts)| counts.executions > 0)
+            .collect();
+        opcodes.sort_by_key(|&(code, counts)| (core::cmp::Reverse(counts.cycles), code));
+        opcodes
+    }
+
+    // Executed addresses, most cycles first. Empty when addresses aren't
+    // profiled
+    pub fn hottest_addresses(&self) -> Vec<(u16, Counts)> {
+        let mut addresses: Vec<(u16, Counts)> = self
+            .addresses
+            .iter()
+            .flatten()
+            .map(|(&address, &counts)| (address, counts))
+            .collect();
+        addresses.sort_by_key(|&(address, counts)| (core::cmp::Reverse(counts.cycles), address));
+        addresses
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.addresses.is_some());
+    }
+
+    // The `limit` hottest opcodes and addresses with their share of the cycles:
+    //   $B1 LDA        1200 runs      6000 cycles  42.1%
+    pub fn report(&self, limit: usize) -> String {
+        let total = self.total().cycles.max(1) as f64;
+        let line = |name: String, counts: Counts| {
+            format!(
+                "  {:<12} {:>10} runs {:>12} cycles {:>5.1}%\n",
+                name,
+                counts.executions,
+                counts.cycles,
+                counts.cycles as f64 * 100.0 / total
+            )
+        };
+        let mut report = String::from("Opcodes:\n");
+        for (code, counts) in self.hottest_opcodes().into_iter().take(limit) {
+            let label = opcodes::CPU_OPCODE_TABLE[code as usize].map_or("???", |op| op.label);
+            report.push_str(&line(format!("${:02X} {}", code, label), counts));
+        }
+        if self.addresses.is_some() {
+            report.push_str("Addresses:\n");
+            for (address, counts) in self.hottest_addresses().into_iter().take(limit) {
+                report.push_str(&line(format!("${:04X}", address), counts));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::new(true);
+        profile.record(0x8000, 0xA9, 2);
+        profile.record(0x8002, 0xCA, 2);
+        profile.record(0x8002, 0xCA, 2);
+        profile.record(0x8003, 0xB1, 6);
+
+        assert_eq!(profile.opcode(0xCA), Counts { executions: 2, cycles: 4 });
+        assert_eq!(profile.address(0x8002), Some(Counts { executions: 2, cycles: 4 }));
+        assert_eq!(profile.address(0x9000), Some(Counts::default()));
+        assert_eq!(profile.total(), Counts { executions: 4, cycles: 12 });
+        let hottest: Vec<u8> = profile.hottest_opcodes().iter().map(|&(code, _)| code).collect();
+        assert_eq!(hottest, vec![0xB1, 0xCA, 0xA9]);
+        assert_eq!(profile.hottest_addresses()[0].0, 0x8003);
+        assert!(profile.report(1).contains("$B1 LDA"));
+
+        profile.reset();
+        assert_eq!(profile.total(), Counts::default());
+        assert_eq!(profile.address(0x8002), Some(Counts::default()));
+        assert_eq!(Profile::new(false).address(0x8002), None);
+    }
+}
@@ -1,4 +1,10 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const SUPPORTED_MAPPERS: [u8; 1] = [0];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 const TRAINER_SIZE: usize = 512;
@@ -10,9 +16,45 @@ pub enum Mirroring {
    FourScreen,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum RomError {
+    InvalidFormat,
+    UnsupportedVersion,
+    UnsupportedMapper(u8),
+    Io(String),
+}
+
+impl RomError {
+    // Supported mappers ordered by how close their number is to the requested one
+    pub fn nearest_supported_mappers(mapper: u8) -> Vec<u8> {
+        let mut mappers = SUPPORTED_MAPPERS.to_vec();
+        mappers.sort_by_key(|supported| supported.abs_diff(mapper));
+        mappers
+    }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::InvalidFormat => write!(f, "Invalid NES file"),
+            RomError::UnsupportedVersion => write!(f, "Only iNES version 1 supported"),
+            RomError::UnsupportedMapper(mapper) => write!(
+                f,
+                "Rom's mapper {} not supported yet (nearest supported: {:?})",
+                mapper,
+                RomError::nearest_supported_mappers(*mapper)
+            ),
+            RomError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for RomError {}
+
 #[derive(Debug, PartialEq)]
 pub struct ROM {
-    trainer: bool,
+    // The 512 bytes loaded at $7000, kept only to write them back out
+    trainer: Option<Vec<u8>>,
     mapper: u8,
     screen_mirroring: Mirroring,
     pub prg_rom: Vec<u8>,
@@ -20,38 +62,110 @@ pub struct ROM {
 }
 
 
+// "mapper 0, 32KB PRG, 8KB CHR, Vertical mirroring, trainer"
+impl fmt::Display for ROM {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mapper {}, {}KB PRG, {}KB CHR, {:?} mirroring",
+            self.mapper,
+            self.prg_rom.len() / 1024,
+            self.chr_rom.len() / 1024,
+            self.screen_mirroring
+        )?;
+        if self.trainer.is_some() {
+            write!(f, ", trainer")?;
+        }
+        Ok(())
+    }
+}
+
 impl ROM {
-     pub fn from_file(file_path: &str) -> Result<Self, String> {
-        let raw = std::fs::read(file_path).map_err(|e| e.to_string())?;
+     #[cfg(feature = "std")]
+     pub fn from_file(file_path: &str) -> Result<Self, RomError> {
+        let raw = std::fs::read(file_path).map_err(|e| RomError::Io(e.to_string()))?;
         Self::new(raw)
     }
 
     pub fn empty() -> Self {
         Self {
-            trainer: false,
+            trainer: None,
             mapper: 0,
             screen_mirroring: Mirroring::Horizontal,
-            prg_rom: vec![0; 0x7FFF],
+            prg_rom: vec![0; 0x8000],
             chr_rom: vec![],
         }
     }
 
-    pub fn new(raw: Vec<u8>) -> Result<Self, String> {
+    pub fn mapper(&self) -> u8 {
+        self.mapper
+    }
+
+    pub fn screen_mirroring(&self) -> &Mirroring {
+        &self.screen_mirroring
+    }
+
+    pub fn is_ines(raw: &[u8]) -> bool {
+        raw.len() >= 16 && raw[0..4] == NES_TAG
+    }
+
+    // Mapper number from an iNES header, whether the mapper is supported or not
+    pub fn header_mapper(raw: &[u8]) -> u8 {
+        raw[7] & 0b1111_0000 | raw[6] >> 4
+    }
+
+    pub fn new(raw: Vec<u8>) -> Result<Self, RomError> {
+        Self::parse(raw, false)
+    }
+
+    // Loads ROMs with an unsupported mapper as mapper 0, some simple hacks still boot
+    pub fn new_with_mapper_fallback(raw: Vec<u8>) -> Result<Self, RomError> {
+        Self::parse(raw, true)
+    }
+
+    // Serializes the cartridge back to an iNES 1.0 file, with whatever was
+    // patched into PRG or CHR since loading. A mapper loaded through the
+    // fallback is written as mapper 0
+    pub fn to_ines(&self) -> Vec<u8> {
+        let (four_screen, vertical) = match self.screen_mirroring {
+            Mirroring::FourScreen => (1, 0),
+            Mirroring::Vertical => (0, 1),
+            Mirroring::Horizontal => (0, 0),
+        };
+        let mut raw = NES_TAG.to_vec();
+        raw.push((self.prg_rom.len() / PRG_ROM_PAGE_SIZE) as u8);
+        raw.push((self.chr_rom.len() / CHR_ROM_PAGE_SIZE) as u8);
+        raw.push(self.mapper << 4 | four_screen << 3 | (self.trainer.is_some() as u8) << 2 | vertical);
+        raw.push(self.mapper & 0b1111_0000);
+        raw.resize(16, 0);
+        if let Some(trainer) = &self.trainer {
+            raw.extend_from_slice(trainer);
+        }
+        raw.extend_from_slice(&self.prg_rom);
+        raw.extend_from_slice(&self.chr_rom);
+        raw
+    }
+
+    fn parse(raw: Vec<u8>, mapper_fallback: bool) -> Result<Self, RomError> {
         // iNES Format
-        if raw[0..4] != NES_TAG {
-            return Err("Invalid NES file".to_string())
+        if raw.len() < 8 || raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidFormat)
         }
 
         // iNES Version
         let version = raw[7] & 0b0000_1100 >> 2;
         if version != 0 {
-            return Err("Only iNES version 1 supported".to_string())
+            return Err(RomError::UnsupportedVersion)
         }
 
         // Mapper
-        let mapper = raw[7] & 0b1111_0000 | raw[6] >> 4;
-        if mapper != 0 {
-            return Err("Rom's mapper not supported yet".to_string())
+        let mut mapper = Self::header_mapper(&raw);
+        if !SUPPORTED_MAPPERS.contains(&mapper) {
+            if !mapper_fallback {
+                return Err(RomError::UnsupportedMapper(mapper))
+            }
+            log!("Warning: mapper {} not supported, loading as mapper 0", mapper);
+            mapper = 0;
         }
         
         // Screen Mirroring
@@ -70,14 +184,18 @@ impl ROM {
         // PRG ROM
         let prg_rom_size: usize = raw[4] as usize * PRG_ROM_PAGE_SIZE;
         let prg_rom_start = 16 + trainer;
+        let chr_rom_size: usize = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        // Truncated files can't hold the banks the header promises
+        if prg_rom_start + prg_rom_size + chr_rom_size > raw.len() {
+            return Err(RomError::InvalidFormat)
+        }
         let prg_rom = raw[prg_rom_start..prg_rom_start + prg_rom_size].to_vec();
         // CHR ROM
-        let chr_rom_size: usize = raw[5] as usize * CHR_ROM_PAGE_SIZE;
         let chr_rom_start = prg_rom_start + prg_rom_size;
         let chr_rom = raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec();
         
         Ok(Self {
-            trainer: trainer > 0,
+            trainer: (trainer > 0).then(|| raw[16..prg_rom_start].to_vec()),
             mapper,
             screen_mirroring,
             prg_rom,
@@ -95,7 +213,18 @@ mod tests {
         let rom = ROM::new(vec![0x00, 0x01, 0x02, 0x03]);
         assert!(rom.is_err());
         let e = rom.unwrap_err();
-        assert_eq!(e, "Invalid NES file");
+        assert_eq!(e.to_string(), "Invalid NES file");
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let mut rom_raw: Vec<u8> = vec![0x00; 16 + PRG_ROM_PAGE_SIZE];
+        rom_raw[0..4].copy_from_slice(&NES_TAG);
+        rom_raw[4] = 0x01;
+        rom_raw[5] = 0x01;
+        // One PRG bank, but the CHR bank is missing
+        assert_eq!(ROM::new(rom_raw), Err(RomError::InvalidFormat));
+        assert_eq!(ROM::new(NES_TAG.to_vec()), Err(RomError::InvalidFormat));
     }
 
     #[test]
@@ -103,7 +232,7 @@ mod tests {
         let rom = ROM::new(vec![0x4E, 0x45, 0x53, 0x1A, 0x00, 0x00, 0x00, 0x01]);
         assert!(rom.is_err());
         let e = rom.unwrap_err();
-        assert_eq!(e, "Only iNES version 1 supported");
+        assert_eq!(e.to_string(), "Only iNES version 1 supported");
     }
 
     #[test]
@@ -111,7 +240,17 @@ mod tests {
         let rom = ROM::new(vec![0x4E, 0x45, 0x53, 0x1A, 0x00, 0x00, 0x00, 0xF0]);
         assert!(rom.is_err());
         let e = rom.unwrap_err();
-        assert_eq!(e, "Rom's mapper not supported yet");
+        assert_eq!(e, RomError::UnsupportedMapper(0xF0));
+        assert_eq!(e.to_string(), "Rom's mapper 240 not supported yet (nearest supported: [0])");
+    }
+
+    #[test]
+    fn test_rom_with_unsupported_mapper_fallback() {
+        let mut rom_raw: Vec<u8> = vec![0x00; 1024];
+        rom_raw[0..4].copy_from_slice(&NES_TAG);
+        rom_raw[6] = 0b0100_0000;
+        let rom = ROM::new_with_mapper_fallback(rom_raw).unwrap();
+        assert_eq!(rom.mapper, 0);
     }
 
     #[test]
@@ -140,13 +279,18 @@ mod tests {
         assert_eq!(rom.unwrap().screen_mirroring, Mirroring::Vertical);
     }
 
+    #[test]
+    fn test_display() {
+        assert_eq!(ROM::empty().to_string(), "mapper 0, 32KB PRG, 0KB CHR, Horizontal mirroring");
+    }
+
     #[test]
     fn test_rom_with_trainer() {
         let mut rom_raw: Vec<u8> = vec![0x00; 1024];
         rom_raw[0..4].copy_from_slice(&NES_TAG);
         rom_raw[6] = 0b0000_0100;
         let rom = ROM::new(rom_raw);
-        assert!(rom.unwrap().trainer);
+        assert!(rom.unwrap().trainer.is_some());
     }
 
     #[test]
@@ -154,7 +298,7 @@ mod tests {
         let mut rom_raw: Vec<u8> = vec![0x00; 1024];
         rom_raw[0..4].copy_from_slice(&NES_TAG);
         let rom = ROM::new(rom_raw);
-        assert!(!rom.unwrap().trainer);
+        assert!(rom.unwrap().trainer.is_none());
     }
 
     #[test]
@@ -192,4 +336,21 @@ mod tests {
         assert_eq!(rom.chr_rom, vec![0x02; CHR_ROM_PAGE_SIZE]);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_to_ines() {
+        let mut rom_raw: Vec<u8> = vec![0x00; 16 + TRAINER_SIZE + PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE];
+        rom_raw[0..4].copy_from_slice(&NES_TAG);
+        rom_raw[4] = 0x01;
+        rom_raw[5] = 0x01;
+        rom_raw[6] = 0b0000_0101;
+        rom_raw[16] = 0x7A;
+        let mut rom = ROM::new(rom_raw.clone()).unwrap();
+        assert_eq!(rom.to_ines(), rom_raw);
+
+        // Patches end up in the file
+        rom.prg_rom[0] = 0xEA;
+        let patched = ROM::new(rom.to_ines()).unwrap();
+        assert_eq!(patched, rom);
+        assert_eq!(ROM::new(ROM::empty().to_ines()), Ok(ROM::empty()));
+    }
+}
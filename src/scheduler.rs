@@ -0,0 +1,87 @@
+use alloc::collections::BTreeMap;
+
+// Delayed hardware effects (register write latencies, NMI delay, DMC start...)
+// keyed on the CPU cycle they fire at. Events due on the same cycle fire in
+// the order they were scheduled.
+pub struct Scheduler<E> {
+    events: BTreeMap<(u64, u64), E>,
+    sequence: u64,
+}
+
+impl<E> Scheduler<E> {
+    pub fn new() -> Self {
+        Self {
+            events: BTreeMap::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn schedule(&mut self, cycle: u64, event: E) {
+        self.events.insert((cycle, self.sequence), event);
+        self.sequence += 1;
+    }
+
+    pub fn schedule_in(&mut self, now: u64, delay: u64, event: E) {
+        self.schedule(now + delay, event);
+    }
+
+    pub fn next_cycle(&self) -> Option<u64> {
+        self.events.keys().next().map(|(cycle, _)| *cycle)
+    }
+
+    // Pending events with the cycle they fire at, in firing order
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &E)> {
+        self.events.iter().map(|((cycle, _), event)| (*cycle, event))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Returns the next event due at or before `now`
+    pub fn pop_due(&mut self, now: u64) -> Option<E> {
+        if self.next_cycle()? > now {
+            return None;
+        }
+        self.events.pop_first().map(|(_, event)| event)
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_fire_in_cycle_then_schedule_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule_in(10, 4, "frame counter reset");
+        scheduler.schedule(12, "nmi");
+        scheduler.schedule(12, "dmc start");
+
+        assert_eq!(scheduler.next_cycle(), Some(12));
+        assert_eq!(scheduler.pop_due(11), None);
+        assert_eq!(scheduler.pop_due(12), Some("nmi"));
+        assert_eq!(scheduler.pop_due(12), Some("dmc start"));
+        assert_eq!(scheduler.pop_due(13), None);
+        assert_eq!(scheduler.pop_due(14), Some("frame counter reset"));
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_iter_lists_pending_events() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(20, "irq");
+        scheduler.schedule(5, "nmi");
+        assert_eq!(scheduler.iter().collect::<Vec<_>>(), [(5, &"nmi"), (20, &"irq")]);
+    }
+}
@@ -0,0 +1,534 @@
+use std::fmt::{self, Write as _};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::bus::Bus;
+use crate::cpu::{AddressingMode, CpuError, Mem, CPU};
+use crate::opcodes::{self, OpCode};
+use crate::rom::ROM;
+
+// Every program is loaded here and ends on the BRK right after the instruction
+// under test (or at JUMP_TARGET for jumps and returns)
+const PROGRAM: u16 = 0x0600;
+const JUMP_TARGET: u16 = 0x0610;
+
+// Operand bytes for each addressing mode resolve to ZERO_PAGE_EA, or to
+// ABSOLUTE_PAGE plus the vector's offset, whatever X and Y are. The indexed
+// zero page operands wrap when X or Y is large, the indexed absolute ones
+// cross a page when the offset is below the index
+const ZERO_PAGE_EA: u16 = 0x10;
+const INDIRECT_X_POINTER: u8 = 0x20;
+const INDIRECT_Y_POINTER: u8 = 0x30;
+const ABSOLUTE_PAGE: u16 = 0x0300;
+
+const N: u8 = 0b1000_0000;
+const V: u8 = 0b0100_0000;
+const B: u8 = 0b0001_0000;
+const D: u8 = 0b0000_1000;
+const I: u8 = 0b0000_0100;
+const Z: u8 = 0b0000_0010;
+const C: u8 = 0b0000_0001;
+
+// Starting registers and operand value for one run of an opcode
+#[derive(Debug, Clone, Copy)]
+pub struct Vector {
+    pub a: u8,
+    pub m: u8,
+    pub p: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    // Low byte of the operand address in the absolute modes
+    pub offset: u8,
+}
+
+impl Vector {
+    const fn new(a: u8, m: u8, p: u8) -> Self {
+        Self { a, m, p, x: 0x04, y: 0x08, sp: 0xFB, offset: 0x00 }
+    }
+
+    fn absolute_ea(&self) -> u16 {
+        ABSOLUTE_PAGE + self.offset as u16
+    }
+}
+
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "A={:02X} M={:02X} P={:02X} X={:02X} Y={:02X} SP={:02X} EA=${:04X}",
+            self.a,
+            self.m,
+            self.p,
+            self.x,
+            self.y,
+            self.sp,
+            self.absolute_ea()
+        )
+    }
+}
+
+const VECTORS: [Vector; 4] = [
+    Vector::new(0x35, 0x42, 0b0010_0100),
+    Vector::new(0xC0, 0xC0, N | V | 0b0010_0000 | I | Z | C),
+    Vector::new(0x7F, 0x01, 0b0010_0100),
+    Vector::new(0x81, 0x7E, 0b0010_0100),
+];
+
+#[derive(Debug, PartialEq)]
+struct State {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    // Memory byte the instruction is expected to leave behind
+    mem: Option<(u16, u8)>,
+    cycles: u16,
+}
+
+fn nz(p: u8, value: u8) -> u8 {
+    let mut p = p & !(N | Z);
+    if value == 0 {
+        p |= Z;
+    }
+    p | (value & N)
+}
+
+fn with_flag(p: u8, flag: u8, set: bool) -> u8 {
+    if set {
+        p | flag
+    } else {
+        p & !flag
+    }
+}
+
+fn add(state: &mut State, m: u8) {
+    let sum = state.a as u16 + m as u16 + (state.p & C) as u16;
+    let result = sum as u8;
+    let overflow = (!(state.a ^ m) & (state.a ^ result) & 0x80) != 0;
+    state.p = with_flag(nz(state.p, result), V, overflow);
+    state.p = with_flag(state.p, C, sum > 0xFF);
+    state.a = result;
+}
+
+fn compare(state: &mut State, register: u8, m: u8) {
+    state.p = with_flag(nz(state.p, register.wrapping_sub(m)), C, register >= m);
+}
+
+// Shifts and rotates, shared by the accumulator and memory forms
+fn shift(label: &str, p: u8, value: u8) -> (u8, u8) {
+    let carry_in = p & C;
+    let (result, carry_out) = match label {
+        "ASL" => (value << 1, value & 0x80 != 0),
+        "LSR" => (value >> 1, value & 0x01 != 0),
+        "ROL" => (value << 1 | carry_in, value & 0x80 != 0),
+        _ => (value >> 1 | carry_in << 7, value & 0x01 != 0),
+    };
+    (result, with_flag(nz(p, result), C, carry_out))
+}
+
+fn branch_taken(label: &str, p: u8) -> bool {
+    match label {
+        "BCC" => p & C == 0,
+        "BCS" => p & C != 0,
+        "BNE" => p & Z == 0,
+        "BEQ" => p & Z != 0,
+        "BPL" => p & N == 0,
+        "BMI" => p & N != 0,
+        "BVC" => p & V == 0,
+        _ => p & V != 0,
+    }
+}
+
+fn operands(mode: &AddressingMode, vector: &Vector) -> (Vec<u8>, Option<u16>) {
+    let ea = vector.absolute_ea();
+    let [low, high] = ea.to_le_bytes();
+    let indexed = |index: u8| ea.wrapping_sub(index as u16).to_le_bytes().to_vec();
+    match mode {
+        AddressingMode::Immediate => (vec![vector.m], None),
+        AddressingMode::ZeroPage => (vec![ZERO_PAGE_EA as u8], Some(ZERO_PAGE_EA)),
+        AddressingMode::ZeroPage_X => (vec![(ZERO_PAGE_EA as u8).wrapping_sub(vector.x)], Some(ZERO_PAGE_EA)),
+        AddressingMode::ZeroPage_Y => (vec![(ZERO_PAGE_EA as u8).wrapping_sub(vector.y)], Some(ZERO_PAGE_EA)),
+        AddressingMode::Absolute => (vec![low, high], Some(ea)),
+        AddressingMode::Absolute_X => (indexed(vector.x), Some(ea)),
+        AddressingMode::Absolute_Y => (indexed(vector.y), Some(ea)),
+        AddressingMode::Indirect_X => (vec![INDIRECT_X_POINTER.wrapping_sub(vector.x)], Some(ea)),
+        AddressingMode::Indirect_Y => (vec![INDIRECT_Y_POINTER], Some(ea)),
+        AddressingMode::NoneAddressing => (vec![], None),
+    }
+}
+
+// The extra cycle of reads whose indexed address lands in the next page
+fn page_cross_cycles(label: &str, mode: &AddressingMode, vector: &Vector) -> u16 {
+    let crossed = match mode {
+        AddressingMode::Absolute_X => vector.offset < vector.x,
+        AddressingMode::Absolute_Y | AddressingMode::Indirect_Y => vector.offset < vector.y,
+        _ => false,
+    };
+    let reads = matches!(
+        label,
+        "ADC" | "AND" | "CMP" | "EOR" | "LAX" | "LDA" | "LDX" | "LDY" | "NOP" | "ORA" | "SBC"
+    );
+    (crossed && reads) as u16
+}
+
+// Loads the program and the memory it needs, returns the state the reference
+// 6502 model expects after running it
+fn prepare(cpu: &mut CPU, opcode: &OpCode, vector: &Vector) -> State {
+    let Vector { a, m, p, x, y, sp, .. } = *vector;
+    let (mut program, ea) = operands(&opcode.addressing_mode, vector);
+    program.insert(0, opcode.opcode);
+    let label = opcode.label;
+    let mut state = State {
+        pc: PROGRAM + opcode.bytes as u16 + 1,
+        a,
+        x,
+        y,
+        p,
+        sp,
+        mem: None,
+        cycles: opcode.cycles + page_cross_cycles(label, &opcode.addressing_mode, vector),
+    };
+
+    // Pointers for the indirect modes and the operand value
+    cpu.write_mem_u16(INDIRECT_X_POINTER as u16, vector.absolute_ea());
+    cpu.write_mem_u16(INDIRECT_Y_POINTER as u16, vector.absolute_ea().wrapping_sub(y as u16));
+    if let Some(ea) = ea {
+        cpu.write_mem(ea, m);
+    }
+    // The stack pointer wraps within page 1
+    let stack = |offset: i8| 0x0100 | sp.wrapping_add(offset as u8) as u16;
+
+    match label {
+        "ADC" => add(&mut state, m),
+        "SBC" => add(&mut state, !m),
+        "AND" => state.a = a & m,
+        "ORA" => state.a = a | m,
+        "EOR" => state.a = a ^ m,
+        "ASL" | "LSR" | "ROL" | "ROR" => match ea {
+            Some(ea) => {
+                let (result, status) = shift(label, p, m);
+                state.p = status;
+                state.mem = Some((ea, result));
+            }
+            None => (state.a, state.p) = shift(label, p, a),
+        },
+        "BIT" => {
+            state.p = with_flag(p & !(N | V) | (m & (N | V)), Z, a & m == 0);
+        }
+        "CMP" => compare(&mut state, a, m),
+        "CPX" => compare(&mut state, x, m),
+        "CPY" => compare(&mut state, y, m),
+        "DEC" | "INC" => {
+            let result = if label == "DEC" { m.wrapping_sub(1) } else { m.wrapping_add(1) };
+            state.p = nz(p, result);
+            state.mem = Some((ea.unwrap(), result));
+        }
+        "DEX" => state.x = x.wrapping_sub(1),
+        "DEY" => state.y = y.wrapping_sub(1),
+        "INX" => state.x = x.wrapping_add(1),
+        "INY" => state.y = y.wrapping_add(1),
+        "LDA" => state.a = m,
+        "LDX" => state.x = m,
+        "LDY" => state.y = m,
+        "STA" => state.mem = Some((ea.unwrap(), a)),
+        "STX" => state.mem = Some((ea.unwrap(), x)),
+        "STY" => state.mem = Some((ea.unwrap(), y)),
+        "TAX" => state.x = a,
+        "TAY" => state.y = a,
+        "TXA" => state.a = x,
+        "TYA" => state.a = y,
+        "TSX" => state.x = sp,
+        "TXS" => state.sp = x,
+        "CLC" => state.p &= !C,
+        "CLD" => state.p &= !D,
+        "CLI" => state.p &= !I,
+        "CLV" => state.p &= !V,
+        "SEC" => state.p |= C,
+        "SED" => state.p |= D,
+        "SEI" => state.p |= I,
+        "NOP" => {}
+        // Stops the run where it is
+        "BRK" => state.pc = PROGRAM + 1,
+        "PHA" => {
+            state.sp = sp.wrapping_sub(1);
+            state.mem = Some((stack(0), a));
+        }
+        "PHP" => {
+            state.sp = sp.wrapping_sub(1);
+            state.mem = Some((stack(0), p | B | 0b0010_0000));
+        }
+        "PLA" => {
+            cpu.write_mem(stack(1), m);
+            state.sp = sp.wrapping_add(1);
+            state.a = m;
+        }
+        "PLP" => {
+            cpu.write_mem(stack(1), m);
+            state.sp = sp.wrapping_add(1);
+            state.p = m & !B | 0b0010_0000;
+        }
+        "BCC" | "BCS" | "BNE" | "BEQ" | "BPL" | "BMI" | "BVC" | "BVS" => {
+            // Skips the BRK right after the branch when taken, in the same page
+            program.push(0x01);
+            if branch_taken(label, p) {
+                state.pc += 1;
+                state.cycles += 1;
+            }
+        }
+        "JMP" | "JSR" => {
+            let [low, high] = JUMP_TARGET.to_le_bytes();
+            program.truncate(1);
+            if opcode.opcode == 0x6C {
+                cpu.write_mem_u16(ABSOLUTE_PAGE, JUMP_TARGET);
+                program.extend(ABSOLUTE_PAGE.to_le_bytes());
+            } else {
+                program.extend([low, high]);
+            }
+            state.pc = JUMP_TARGET + 1;
+            if label == "JSR" {
+                // Return address minus one, high byte first
+                state.sp = sp.wrapping_sub(2);
+                state.mem = Some((stack(-1), (PROGRAM + 2) as u8));
+            }
+        }
+        "RTS" => {
+            let [low, high] = (JUMP_TARGET - 1).to_le_bytes();
+            cpu.write_mem(stack(1), low);
+            cpu.write_mem(stack(2), high);
+            state.sp = sp.wrapping_add(2);
+            state.pc = JUMP_TARGET + 1;
+        }
+        "RTI" => {
+            let [low, high] = JUMP_TARGET.to_le_bytes();
+            cpu.write_mem(stack(1), m);
+            cpu.write_mem(stack(2), low);
+            cpu.write_mem(stack(3), high);
+            state.sp = sp.wrapping_add(3);
+            state.p = m & !B | 0b0010_0000;
+            state.pc = JUMP_TARGET + 1;
+        }
+        // Unofficial opcodes
+        "ALR" => (state.a, state.p) = shift("LSR", p, a & m),
+        "ANC" => {
+            state.a = a & m;
+            state.p = with_flag(p, C, state.a & N != 0);
+        }
+        "ARR" => {
+            let (result, _) = shift("ROR", p, a & m);
+            state.a = result;
+            state.p = with_flag(nz(p, result), C, result & 0x40 != 0);
+            state.p = with_flag(state.p, V, (result >> 6 ^ result >> 5) & 1 != 0);
+        }
+        "AXS" => {
+            let masked = a & x;
+            state.x = masked.wrapping_sub(m);
+            state.p = with_flag(nz(p, state.x), C, masked >= m);
+        }
+        "DCP" => {
+            let result = m.wrapping_sub(1);
+            state.mem = Some((ea.unwrap(), result));
+            compare(&mut state, a, result);
+        }
+        "ISB" => {
+            let result = m.wrapping_add(1);
+            state.mem = Some((ea.unwrap(), result));
+            add(&mut state, !result);
+        }
+        "LAX" => {
+            state.a = m;
+            state.x = m;
+        }
+        "SAX" => state.mem = Some((ea.unwrap(), a & x)),
+        "SLO" | "RLA" | "SRE" | "RRA" => {
+            let shifted = match label {
+                "SLO" => "ASL",
+                "RLA" => "ROL",
+                "SRE" => "LSR",
+                _ => "ROR",
+            };
+            let (result, status) = shift(shifted, p, m);
+            state.p = status;
+            state.mem = Some((ea.unwrap(), result));
+            match label {
+                "SLO" => state.a = a | result,
+                "RLA" => state.a = a & result,
+                "SRE" => state.a = a ^ result,
+                _ => add(&mut state, result),
+            }
+        }
+        _ => unreachable!("No self-test model for {}", label),
+    }
+    if matches!(label, "AND" | "ORA" | "EOR" | "LDA" | "TXA" | "TYA" | "PLA" | "ANC" | "LAX" | "SLO" | "RLA" | "SRE") {
+        state.p = nz(state.p, state.a);
+    }
+    if matches!(label, "DEX" | "INX" | "LDX" | "TAX" | "TSX") {
+        state.p = nz(state.p, state.x);
+    }
+    if matches!(label, "DEY" | "INY" | "LDY" | "TAY") {
+        state.p = nz(state.p, state.y);
+    }
+
+    program.push(0x00);
+    cpu.load_raw(&program, PROGRAM, PROGRAM);
+    cpu.reset();
+    cpu.register_accumulator = a;
+    cpu.index_register_x = x;
+    cpu.index_register_y = y;
+    cpu.status.status = p;
+    cpu.stack_pointer = sp;
+    state
+}
+
+fn actual_state(cpu: &CPU, expected: &State, cycles: u16) -> State {
+    State {
+        pc: cpu.program_counter,
+        a: cpu.register_accumulator,
+        x: cpu.index_register_x,
+        y: cpu.index_register_y,
+        p: cpu.status.status,
+        sp: cpu.stack_pointer,
+        mem: expected.mem.map(|(addr, _)| (addr, cpu.peek_mem(addr))),
+        cycles,
+    }
+}
+
+// Runs the instruction under test, then on to the final BRK. Returns the
+// cycles of the instruction under test
+fn run_program(cpu: &mut CPU) -> Result<u16, CpuError> {
+    let mut instructions = cpu.instructions();
+    let cycles = instructions.next().transpose()?.map_or(0, |record| record.cycles);
+    for record in instructions {
+        record?;
+    }
+    Ok(cycles)
+}
+
+// Runs one opcode from a starting vector and compares the result with the
+// reference model
+pub fn check_vector(opcode: &OpCode, vector: Vector) -> Result<(), String> {
+    let mut cpu = CPU::new(Bus::new(ROM::empty()));
+    cpu.set_trace(false);
+    let expected = prepare(&mut cpu, opcode, &vector);
+    let cycles = panic::catch_unwind(AssertUnwindSafe(|| run_program(&mut cpu)))
+        .map_err(|_| format!("panicked with {}", vector))?
+        .map_err(|e| format!("{} with {}", e, vector))?;
+    let actual = actual_state(&cpu, &expected, cycles);
+    if actual != expected {
+        return Err(format!("{}: expected {:02X?}, got {:02X?}", vector, expected, actual));
+    }
+    Ok(())
+}
+
+// Runs one opcode against every vector, returning the first mismatch
+pub fn check_opcode(opcode: &OpCode) -> Result<(), String> {
+    VECTORS.into_iter().try_for_each(|vector| check_vector(opcode, vector))
+}
+
+// Every opcode but KIL, which stops the CPU instead of reaching the BRK
+fn testable(opcode: &&OpCode) -> bool {
+    opcode.label != "KIL"
+}
+
+pub struct Report {
+    pub results: Vec<(&'static OpCode, Result<(), String>)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    // One row per mnemonic, one column per addressing mode
+    pub fn matrix(&self) -> String {
+        const COLUMNS: [(&str, &str); 10] = [
+            ("Imp", "NoneAddressing"),
+            ("Imm", "Immediate"),
+            ("ZP", "ZeroPage"),
+            ("ZPX", "ZeroPage_X"),
+            ("ZPY", "ZeroPage_Y"),
+            ("Abs", "Absolute"),
+            ("AbX", "Absolute_X"),
+            ("AbY", "Absolute_Y"),
+            ("IzX", "Indirect_X"),
+            ("IzY", "Indirect_Y"),
+        ];
+        let mut out = String::from("     ");
+        for (name, _) in COLUMNS {
+            let _ = write!(out, " {:<4}", name);
+        }
+        let mut labels: Vec<&str> = self.results.iter().map(|(op, _)| op.label).collect();
+        labels.sort_unstable();
+        labels.dedup();
+        for label in labels {
+            let _ = write!(out, "\n{:<5}", label);
+            for (_, mode) in COLUMNS {
+                let cell = self
+                    .results
+                    .iter()
+                    .filter(|(op, _)| op.label == label && format!("{:?}", op.addressing_mode) == mode)
+                    .map(|(_, result)| if result.is_ok() { "ok" } else { "FAIL" })
+                    .reduce(|a, b| if a == "ok" { b } else { a })
+                    .unwrap_or(".");
+                let _ = write!(out, " {:<4}", cell);
+            }
+        }
+        for (op, result) in &self.results {
+            if let Err(e) = result {
+                let _ = write!(out, "\n{:02X} {} {:?}: {}", op.opcode, op.label, op.addressing_mode, e);
+            }
+        }
+        out.lines().map(str::trim_end).collect::<Vec<&str>>().join("\n")
+    }
+}
+
+// Checks every opcode, unofficial ones included, on the real bus
+pub fn run() -> Report {
+    let results = opcodes::all()
+        .iter()
+        .filter(testable)
+        .map(|op| (op, check_opcode(op)))
+        .collect();
+    Report { results }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const RANDOM_VECTORS: usize = 100;
+
+    #[test]
+    fn test_selftest_passes() {
+        let report = run();
+        assert!(report.passed(), "{}", report.matrix());
+    }
+
+    // Differential test: random registers, operand and operand address for
+    // every opcode, on this core and on the reference model, cycles included.
+    // The seed is fixed so a failure always reproduces.
+    #[test]
+    fn test_random_vectors_match_reference() {
+        let mut rng = StdRng::seed_from_u64(0x6502);
+        for opcode in opcodes::all().iter().filter(testable) {
+            for _ in 0..RANDOM_VECTORS {
+                let vector = Vector {
+                    a: rng.gen(),
+                    m: rng.gen(),
+                    // Bit 5 always reads back set and B only exists on the stack
+                    p: rng.gen::<u8>() & !B | 0b0010_0000,
+                    x: rng.gen(),
+                    y: rng.gen(),
+                    sp: rng.gen(),
+                    offset: rng.gen(),
+                };
+                if let Err(e) = check_vector(opcode, vector) {
+                    panic!("{:02X} {} {:?}: {}", opcode.opcode, opcode.label, opcode.addressing_mode, e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::Write as _;
+
+// Host input recorded by a frontend, to replay bug reports exactly.
+// Events are keyed by emulation step (callback invocation), the wall-clock
+// time is kept only as a hint for whoever reads the file.
+#[derive(Debug, PartialEq)]
+pub struct SessionEvent {
+    pub step: u64,
+    pub millis: u128,
+    pub key: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub version: String,
+    pub seed: u64,
+    pub events: Vec<SessionEvent>,
+}
+
+impl Session {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            version: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            seed,
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, step: u64, millis: u128, key: &str) {
+        self.events.push(SessionEvent {
+            step,
+            millis,
+            key: key.to_string(),
+        });
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!("{}\nseed {}\n", self.version, self.seed);
+        for event in &self.events {
+            let _ = writeln!(out, "{} {} {}", event.step, event.millis, event.key);
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        let version = lines.next().ok_or("Empty session file")?.to_string();
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .and_then(|seed| seed.parse().ok())
+            .ok_or("Missing session seed")?;
+
+        let mut events = vec![];
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [step, millis, key] = fields[..] else {
+                return Err(format!("Invalid session event: {}", line));
+            };
+            events.push(SessionEvent {
+                step: step.parse().map_err(|_| format!("Invalid session event: {}", line))?,
+                millis: millis.parse().map_err(|_| format!("Invalid session event: {}", line))?,
+                key: key.to_string(),
+            });
+        }
+        Ok(Self { version, seed, events })
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), String> {
+        std::fs::write(file_path, self.serialize()).map_err(|e| e.to_string())
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let mut session = Session::new(42);
+        session.record(100, 16, "W");
+        session.record(250, 40, "A");
+        let parsed = Session::parse(&session.serialize()).unwrap();
+        assert_eq!(parsed, session);
+    }
+
+    #[test]
+    fn test_session_with_invalid_event() {
+        let session = Session::parse("nes_emulator 0.1.0\nseed 1\n12 W\n");
+        assert_eq!(session.unwrap_err(), "Invalid session event: 12 W");
+    }
+}
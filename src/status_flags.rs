@@ -1,3 +1,5 @@
+use core::fmt;
+
 pub enum StatusFlag {
     Carry,            // Bit 0
     Zero,             // Bit 1
@@ -18,6 +20,28 @@ pub struct ProcessorStatus {
     pub status: u8,
 }
 
+// Flags in NV-BDIZC order, uppercase when set: "nV-bdIzC"
+impl fmt::Display for ProcessorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (bit, letter) in "NV-BDIZC".chars().enumerate() {
+            let set = self.status & (0x80 >> bit) != 0;
+            let c = match letter {
+                '-' => '-',
+                _ if set => letter,
+                _ => letter.to_ascii_lowercase(),
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ProcessorStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ProcessorStatus {
     pub fn new() -> Self {
         Self {
@@ -60,8 +84,8 @@ impl ProcessorStatus {
 
     pub fn set_flag(&mut self, flag: StatusFlag, bit: bool) {
         match bit {
-            true => self.status = self.status | self.get_mask(flag).set,
-            false => self.status = self.status & self.get_mask(flag).unset,
+            true => self.status |= self.get_mask(flag).set,
+            false => self.status &= self.get_mask(flag).unset,
         };
     }
 
@@ -90,4 +114,10 @@ mod tests {
         p.set_flag(StatusFlag::Carry, true);
         assert_eq!(p.status, 0b0010_0001);
     }
+
+    #[test]
+    fn test_display() {
+        let p = ProcessorStatus { status: 0b0110_0101 };
+        assert_eq!(p.to_string(), "nV-bdIzC");
+    }
 }
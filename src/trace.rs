@@ -0,0 +1,291 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::cpu::{AddressingMode, Mem, CPU};
+use crate::debugger::eval_expr;
+use crate::disasm::format_operand;
+use crate::opcodes::{Instruction, OpCode, CPU_OPCODE_TABLE};
+
+// The reset sequence takes 7 cycles before the first instruction
+const RESET_CYCLES: u64 = 7;
+const DOTS_PER_SCANLINE: u64 = 341;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+// Operand as nestest.log shows it: the disassembly plus the effective
+// address and the value there before the instruction runs
+fn annotated_operand(cpu: &CPU, opcode: &OpCode, args: &[u8]) -> String {
+    let peek = |addr: u16| cpu.peek_mem(addr);
+    let peek_u16 = |lo: u16, hi: u16| u16::from_le_bytes([peek(lo), peek(hi)]);
+    let (x, y) = (cpu.index_register_x, cpu.index_register_y);
+    let base = format_operand(opcode, cpu.program_counter, args, &BTreeMap::new());
+    let word = || u16::from_le_bytes([args[0], args[1]]);
+    match opcode.addressing_mode {
+        AddressingMode::Immediate => base,
+        AddressingMode::ZeroPage => format!("{} = {:02X}", base, peek(args[0] as u16)),
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            let index = if matches!(opcode.addressing_mode, AddressingMode::ZeroPage_X) { x } else { y };
+            let addr = args[0].wrapping_add(index);
+            format!("{} @ {:02X} = {:02X}", base, addr, peek(addr as u16))
+        }
+        AddressingMode::Absolute => match opcode.instruction {
+            Instruction::JMP | Instruction::JSR => base,
+            _ => format!("{} = {:02X}", base, peek(word())),
+        },
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            let index = if matches!(opcode.addressing_mode, AddressingMode::Absolute_X) { x } else { y };
+            let addr = word().wrapping_add(index as u16);
+            format!("{} @ {:04X} = {:02X}", base, addr, peek(addr))
+        }
+        AddressingMode::Indirect_X => {
+            let ptr = args[0].wrapping_add(x);
+            let addr = peek_u16(ptr as u16, ptr.wrapping_add(1) as u16);
+            format!("{} @ {:02X} = {:04X} = {:02X}", base, ptr, addr, peek(addr))
+        }
+        AddressingMode::Indirect_Y => {
+            let pointer = peek_u16(args[0] as u16, args[0].wrapping_add(1) as u16);
+            let addr = pointer.wrapping_add(y as u16);
+            format!("{} = {:04X} @ {:04X} = {:02X}", base, pointer, addr, peek(addr))
+        }
+        // JMP indirect, with the page wrap bug
+        AddressingMode::NoneAddressing if opcode.bytes == 3 => {
+            let ptr = word();
+            let target = peek_u16(ptr, ptr & 0xFF00 | ptr.wrapping_add(1) & 0x00FF);
+            format!("{} = {:04X}", base, target)
+        }
+        AddressingMode::NoneAddressing => base,
+    }
+}
+
+// One line in the nestest.log format for the instruction at PC, `cycles`
+// being the CPU cycles elapsed since power-up. The PPU position is derived
+// from the cycle count (3 dots per cycle)
+pub fn nestest_line(cpu: &CPU, cycles: u64) -> String {
+    let pc = cpu.program_counter;
+    let code = cpu.peek_mem(pc);
+    let (mnemonic, operand, args) = match CPU_OPCODE_TABLE[code as usize] {
+        Some(opcode) => {
+            let args: Vec<u8> = (1..opcode.bytes as u16).map(|i| cpu.peek_mem(pc.wrapping_add(i))).collect();
+            let star = if opcode.official { "" } else { "*" };
+            (format!("{}{}", star, opcode.label), annotated_operand(cpu, opcode, &args), args)
+        }
+        None => (String::from("???"), String::new(), Vec::new()),
+    };
+    let hex: Vec<String> = core::iter::once(&code).chain(&args).map(|b| format!("{:02X}", b)).collect();
+    let disassembly = format!("{:04X}  {:<8} {:>4} {}", pc, hex.join(" "), mnemonic, operand);
+    let dots = cycles * 3;
+    format!(
+        "{:<47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        disassembly.trim_end(),
+        cpu.register_accumulator,
+        cpu.index_register_x,
+        cpu.index_register_y,
+        cpu.status.status,
+        cpu.stack_pointer,
+        dots / DOTS_PER_SCANLINE % SCANLINES_PER_FRAME,
+        dots % DOTS_PER_SCANLINE,
+        cycles
+    )
+}
+
+// Produces nestest lines from an execute_with_callback callback. Cycles are
+// the CPU's, counted from the first traced instruction, so page crossings,
+// DMA stalls and interrupts show up like in the golden log
+pub struct NestestTracer {
+    start: Option<u64>,
+}
+
+impl NestestTracer {
+    pub fn new() -> Self {
+        Self { start: None }
+    }
+
+    // Line for the instruction about to run
+    pub fn trace(&mut self, cpu: &CPU) -> String {
+        let start = *self.start.get_or_insert(cpu.cycles);
+        nestest_line(cpu, RESET_CYCLES + cpu.cycles - start)
+    }
+}
+
+impl Default for NestestTracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Selects trace lines, a line is kept if any filter matches it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFilter {
+    // JSR, RTS, RTI and BRK
+    Calls,
+    // Conditional branches and JMP
+    Branches,
+    // Instructions reading or writing an address in start..=end
+    Range(u16, u16),
+    // Instructions that changed A
+    AccumulatorChanges,
+}
+
+impl TraceFilter {
+    // "calls", "branches", "range:$0200..$02FF" or "a"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "calls" => Ok(TraceFilter::Calls),
+            "branches" => Ok(TraceFilter::Branches),
+            "a" => Ok(TraceFilter::AccumulatorChanges),
+            _ => {
+                let range = spec.strip_prefix("range:").ok_or(format!("Unknown trace filter: {}", spec))?;
+                let (start, end) = range.split_once("..").unwrap_or((range, range));
+                Ok(TraceFilter::Range(eval_expr(start)?, eval_expr(end)?))
+            }
+        }
+    }
+}
+
+fn is_call(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::JSR | Instruction::RTS | Instruction::RTI | Instruction::BRK)
+}
+
+fn is_branch(instruction: Instruction) -> bool {
+    use Instruction::*;
+    matches!(instruction, BCC | BCS | BEQ | BMI | BNE | BPL | BVC | BVS | JMP)
+}
+
+// Data address of the instruction at PC, before it runs. JMP and JSR only
+// take their target from the operand
+fn operand_address(cpu: &CPU, opcode: &OpCode) -> Option<u16> {
+    match (opcode.instruction, &opcode.addressing_mode) {
+        (Instruction::JMP | Instruction::JSR, AddressingMode::Absolute) => None,
+        (_, mode) => cpu.peek_operand_address_at(cpu.program_counter, mode),
+    }
+}
+
+struct PendingLine {
+    line: String,
+    accumulator: u8,
+    matched: bool,
+}
+
+// Filters and colors trace lines. Whether an instruction changed A is only
+// known once it ran, so every line comes out one instruction late: push()
+// returns the previous line if it is kept, finish() the last one
+pub struct TraceOutput {
+    filters: Vec<TraceFilter>,
+    color: bool,
+    pending: Option<PendingLine>,
+}
+
+impl TraceOutput {
+    pub fn new(filters: Vec<TraceFilter>, color: bool) -> Self {
+        Self { filters, color, pending: None }
+    }
+
+    // `line` is the trace of the instruction about to run
+    pub fn push(&mut self, cpu: &CPU, line: String) -> Option<String> {
+        let previous = self.finish(cpu);
+        let opcode = CPU_OPCODE_TABLE[cpu.peek_mem(cpu.program_counter) as usize];
+        let matched = self.filters.is_empty()
+            || self.filters.iter().any(|filter| match (filter, opcode) {
+                (TraceFilter::Calls, Some(op)) => is_call(op.instruction),
+                (TraceFilter::Branches, Some(op)) => is_branch(op.instruction),
+                (&TraceFilter::Range(start, end), Some(op)) => {
+                    operand_address(cpu, op).is_some_and(|addr| (start..=end).contains(&addr))
+                }
+                _ => false,
+            });
+        let line = match opcode.filter(|_| self.color) {
+            Some(op) => colorize(op, line),
+            None => line,
+        };
+        self.pending = Some(PendingLine { line, accumulator: cpu.register_accumulator, matched });
+        previous
+    }
+
+    // The held back line, once `cpu` ran its instruction
+    pub fn finish(&mut self, cpu: &CPU) -> Option<String> {
+        let pending = self.pending.take()?;
+        let changed_a = pending.accumulator != cpu.register_accumulator
+            && self.filters.contains(&TraceFilter::AccumulatorChanges);
+        (pending.matched || changed_a).then_some(pending.line)
+    }
+}
+
+// Calls in yellow, branches in cyan, unofficial opcodes in red
+fn colorize(opcode: &OpCode, line: String) -> String {
+    let color = if is_call(opcode.instruction) {
+        "33"
+    } else if is_branch(opcode.instruction) {
+        "36"
+    } else if !opcode.official {
+        "31"
+    } else {
+        return line;
+    };
+    format!("\x1b[{}m{}\x1b[0m", color, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::ROM;
+
+    #[test]
+    fn test_nestest_lines() {
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        // JMP $C5F5, then LDX #$00 and LDA ($80,X) there
+        cpu.load_raw(&[0x4C, 0xF5, 0xC5], 0xC000, 0xC000);
+        cpu.load_raw(&[0xA2, 0x00, 0xA1, 0x80], 0xC5F5, 0xC000);
+        cpu.write_mem_u16(0x80, 0x0200);
+        cpu.write_mem(0x0200, 0x5A);
+        cpu.reset();
+        cpu.stack_pointer = 0xFD;
+        cpu.status.status = 0x24;
+
+        let mut tracer = NestestTracer::new();
+        let mut lines = vec![];
+        for _ in 0..3 {
+            lines.push(tracer.trace(&cpu));
+            cpu.step().unwrap();
+        }
+        assert_eq!(
+            lines,
+            vec![
+                "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+                "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+                "C5F7  A1 80     LDA ($80,X) @ 80 = 0200 = 5A    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        assert_eq!(TraceFilter::parse("range:$0200..$02FF"), Ok(TraceFilter::Range(0x0200, 0x02FF)));
+        assert_eq!(TraceFilter::parse("range:$10"), Ok(TraceFilter::Range(0x10, 0x10)));
+        assert!(TraceFilter::parse("jumps").is_err());
+
+        let mut cpu = CPU::new(Bus::new(ROM::empty()));
+        cpu.set_trace(false);
+        // LDA #$00 / LDA #$01 / STA $0205 / JSR $0610 / ... / RTS
+        cpu.load_raw(&[0xA9, 0x00, 0xA9, 0x01, 0x8D, 0x05, 0x02, 0x20, 0x10, 0x06], 0x0600, 0x0600);
+        cpu.load_raw(&[0x60], 0x0610, 0x0600);
+        cpu.reset();
+        let filters = vec![TraceFilter::Calls, TraceFilter::Range(0x0200, 0x02FF), TraceFilter::AccumulatorChanges];
+        let mut output = TraceOutput::new(filters, false);
+        let mut kept = vec![];
+        for _ in 0..5 {
+            let line = format!("{:04X}", cpu.program_counter);
+            kept.extend(output.push(&cpu, line));
+            cpu.step().unwrap();
+        }
+        kept.extend(output.finish(&cpu));
+        // The first LDA leaves A at 0
+        assert_eq!(kept, vec!["0602", "0604", "0607", "0610"]);
+
+        let mut output = TraceOutput::new(vec![], true);
+        cpu.program_counter = 0x0607;
+        output.push(&cpu, "JSR".to_string());
+        assert_eq!(output.finish(&cpu), Some("\x1b[33mJSR\x1b[0m".to_string()));
+    }
+}